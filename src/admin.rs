@@ -0,0 +1,515 @@
+use std::{borrow::Cow, net::SocketAddr, sync::atomic::Ordering};
+
+use anyhow::{anyhow, Context};
+use http::{Method, Request, Response};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server,
+};
+use serde::Serialize;
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    config::{ErrorFormat, SettingAdmin, SettingHost},
+    consts::get_settings,
+    error::{Error, Result},
+    http::{error_response, mime::APPLICATION_JSON, split::BucketStatus, CandyBody},
+    service::Shutdown,
+    utils::listenfd::take_listener,
+};
+
+/// Host status in admin API
+#[derive(Serialize, Debug)]
+struct AdminHost<'a> {
+    ip: &'a str,
+    port: u32,
+    routes: Vec<&'a str>,
+    filters: Vec<AdminFilter<'a>>,
+    /// Requests rejected by `limit_conn`
+    limit_conn_rejected: u64,
+    splits: Vec<AdminSplit<'a>>,
+    maintenance: bool,
+}
+
+/// Proxy route upstreams in admin API
+#[derive(Serialize, Debug)]
+struct AdminUpstream<'a> {
+    port: u32,
+    location: &'a str,
+    /// `proxy_pass` of route, none when traffic is split
+    proxy_pass: Option<&'a str>,
+    buckets: Vec<BucketStatus<'a>>,
+}
+
+/// Traffic split of route in admin API
+#[derive(Serialize, Debug)]
+struct AdminSplit<'a> {
+    location: &'a str,
+    buckets: Vec<BucketStatus<'a>>,
+}
+
+/// Request filter hits in admin API
+#[derive(Serialize, Debug)]
+struct AdminFilter<'a> {
+    id: &'a str,
+    hits: u64,
+}
+
+impl SettingAdmin {
+    /// Adopt inherited admin listener or bind a new one,
+    /// the address must be loopback
+    ///
+    /// ## Arguments
+    ///
+    /// `inherited`: listeners inherited from parent process
+    pub fn listener(
+        &self,
+        inherited: &mut Vec<std::net::TcpListener>,
+    ) -> anyhow::Result<std::net::TcpListener> {
+        let addr: SocketAddr = self
+            .listen
+            .parse()
+            .with_context(|| format!("invalid admin listen address {}", self.listen))?;
+        if !addr.ip().is_loopback() {
+            return Err(anyhow!("admin listen address {addr} is not loopback"));
+        }
+        take_listener(&self.listen, inherited)
+    }
+
+    /// Serve admin API until shutdown token cancelled
+    ///
+    /// ## Arguments
+    ///
+    /// `listener`: admin listener from [`SettingAdmin::listener`]
+    /// `shutdown`: stop signals of SIGTERM, SIGINT or binary upgrade
+    pub async fn mk_server(
+        &'static self,
+        listener: std::net::TcpListener,
+        shutdown: Shutdown,
+    ) -> anyhow::Result<()> {
+        let listener = TcpListener::from_std(listener)?;
+        info!("admin bind on {}", self.listen);
+
+        let server = server::conn::auto::Builder::new(TokioExecutor::new());
+        loop {
+            let conn = tokio::select! {
+                conn = listener.accept() => conn,
+                _ = shutdown.cancelled() => return Ok(()),
+            };
+            let (stream, peer_addr) = match conn {
+                Ok(conn) => conn,
+                Err(e) => {
+                    error!("admin accept error: {}", e);
+                    continue;
+                }
+            };
+            debug!("admin connection accepted: {}", peer_addr);
+            let service = move |req: Request<hyper::body::Incoming>| async move {
+                let hosts = &get_settings()?.host;
+                let res = handle_admin(self, hosts, &req);
+                info!(
+                    "admin \"{peer_addr}\" {} {} {}",
+                    req.method(),
+                    req.uri(),
+                    res.status()
+                );
+                anyhow::Ok(res)
+            };
+            let conn = server
+                .serve_connection(TokioIo::new(stream), hyper::service::service_fn(service))
+                .into_owned();
+            tokio::spawn(async move {
+                if let Err(err) = conn.await {
+                    error!("admin connection error: {}", err);
+                }
+            });
+        }
+    }
+}
+
+/// Handle admin API request
+///
+/// ## Arguments
+///
+/// `admin`: admin config
+/// `hosts`: virtual hosts from config
+/// `req`: admin client request
+pub fn handle_admin<T>(
+    admin: &SettingAdmin,
+    hosts: &[SettingHost],
+    req: &Request<T>,
+) -> Response<CandyBody<Bytes>> {
+    let authorized = req
+        .headers()
+        .get("Authorization")
+        .and_then(|auth| auth.to_str().ok())
+        .and_then(|auth| auth.strip_prefix("Bearer "))
+        .is_some_and(|token| bool::from(token.as_bytes().ct_eq(admin.token.as_bytes())));
+    if !authorized {
+        warn!("admin unauthorized request {}", req.uri());
+        let err = Error::Unauthorized("admin token mismatch".into());
+        return error_response(&err, ErrorFormat::Json);
+    }
+
+    let path = req.uri().path();
+    let body = match (req.method(), path) {
+        (&Method::GET, "/hosts") => {
+            let hosts = hosts
+                .iter()
+                .map(|host| AdminHost {
+                    ip: &host.ip,
+                    port: host.port,
+                    routes: host.route_map.keys().map(String::as_str).collect(),
+                    filters: host
+                        .filters
+                        .iter()
+                        .map(|filter| AdminFilter {
+                            id: &filter.id,
+                            hits: filter.hits(),
+                        })
+                        .collect(),
+                    limit_conn_rejected: host.conn_limiter.rejected(),
+                    splits: host
+                        .route_map
+                        .iter()
+                        .filter_map(|(location, route)| {
+                            let splitter = route.splitter.as_ref()?;
+                            Some(AdminSplit {
+                                location,
+                                buckets: splitter.status(),
+                            })
+                        })
+                        .collect(),
+                    maintenance: host.maintenance.load(Ordering::Relaxed),
+                })
+                .collect::<Vec<_>>();
+            serde_json::to_string(&hosts)
+        }
+        (&Method::GET, "/upstreams") => {
+            let upstreams = hosts
+                .iter()
+                .flat_map(|host| {
+                    host.route_map.iter().filter_map(|(location, route)| {
+                        let buckets = route
+                            .splitter
+                            .as_ref()
+                            .map(|splitter| splitter.status())
+                            .unwrap_or_default();
+                        let proxy_pass = route.proxy_pass.as_deref();
+                        (proxy_pass.is_some() || !buckets.is_empty()).then_some(AdminUpstream {
+                            port: host.port,
+                            location,
+                            proxy_pass,
+                            buckets,
+                        })
+                    })
+                })
+                .collect::<Vec<_>>();
+            serde_json::to_string(&upstreams)
+        }
+        // same as SIGUSR2, the new process reads config again
+        // and takes over listeners once ready
+        #[cfg(unix)]
+        (&Method::POST, "/reload") => match reload() {
+            Ok(()) => Ok(r#"{"ok":true}"#.to_string()),
+            Err(err) => {
+                error!("admin reload failed {err}");
+                return error_response(&err, ErrorFormat::Json);
+            }
+        },
+        // POST /hosts/4000/maintenance?enabled=true
+        (&Method::POST, path) if path.starts_with("/hosts/") && path.ends_with("/maintenance") => {
+            match set_maintenance(hosts, path, req.uri().query()) {
+                Ok(()) => Ok(r#"{"ok":true}"#.to_string()),
+                Err(err) => {
+                    warn!("admin set maintenance failed {err}");
+                    return error_response(&err, ErrorFormat::Json);
+                }
+            }
+        }
+        // change split weights at runtime
+        // PUT /split?port=4000&location=/api/&weights=90,10
+        (&Method::PUT, "/split") => match set_split_weights(hosts, req.uri().query()) {
+            Ok(()) => Ok(r#"{"ok":true}"#.to_string()),
+            Err(err) => {
+                warn!("admin set split weights failed {err}");
+                return error_response(&err, ErrorFormat::Json);
+            }
+        },
+        (method, path) => {
+            let err = Error::NotFound(format!("admin {method} {path} not found").into());
+            return error_response(&err, ErrorFormat::Json);
+        }
+    };
+    match body {
+        Ok(body) => Response::builder()
+            .header("Content-Type", APPLICATION_JSON)
+            .body(Full::new(body.into()).map_err(|e| match e {}).boxed())
+            .unwrap(),
+        Err(err) => error_response(&Error::InternalServerError(err.into()), ErrorFormat::Json),
+    }
+}
+
+/// Find percent-decoded query string value by name
+fn query_param<'a>(query: Option<&'a str>, name: &str) -> Result<Cow<'a, str>> {
+    form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value)
+        .ok_or_else(|| Error::BadRequest(format!("missing {name}").into()))
+}
+
+/// Reload config by binary upgrade of current process,
+/// only on unix where `upgrade_on_signal` listens
+#[cfg(unix)]
+fn reload() -> Result<()> {
+    // SAFETY: send signal to current process, handled by upgrade_on_signal
+    if unsafe { libc::kill(libc::getpid(), libc::SIGUSR2) } != 0 {
+        return Err(anyhow!("send SIGUSR2 failed {}", std::io::Error::last_os_error()).into());
+    }
+    Ok(())
+}
+
+/// Turn maintenance mode of host on or off
+///
+/// ## Arguments
+///
+/// `hosts`: virtual hosts from config
+/// `path`: `/hosts/4000/maintenance`
+/// `query`: `enabled=true` or `enabled=false`
+fn set_maintenance(hosts: &[SettingHost], path: &str, query: Option<&str>) -> Result<()> {
+    let port = path
+        .trim_start_matches("/hosts/")
+        .trim_end_matches("/maintenance");
+    let enabled = query_param(query, "enabled")?
+        .parse::<bool>()
+        .map_err(|err| Error::BadRequest(format!("invalid enabled {err}").into()))?;
+    let mut found = false;
+    for host in hosts.iter().filter(|host| host.port.to_string() == port) {
+        host.maintenance.store(enabled, Ordering::Relaxed);
+        found = true;
+    }
+    if !found {
+        return Err(Error::NotFound(format!("host {port} not found").into()));
+    }
+    info!("admin host {port} maintenance {enabled}");
+    Ok(())
+}
+
+/// Change weights of route traffic split from admin query string
+///
+/// ## Arguments
+///
+/// `hosts`: virtual hosts from config
+/// `query`: `port=4000&location=/api/&weights=90,10`
+fn set_split_weights(hosts: &[SettingHost], query: Option<&str>) -> Result<()> {
+    let param = |name| query_param(query, name);
+    let port = param("port")?;
+    let location = param("location")?;
+    let weights = param("weights")?
+        .split(',')
+        .map(|weight| weight.trim().parse::<u32>())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|err| Error::BadRequest(format!("invalid weights {err}").into()))?;
+    let splitter = hosts
+        .iter()
+        .filter(|host| host.port.to_string() == port)
+        .find_map(|host| host.route_map.get(location.as_ref())?.splitter.as_ref())
+        .ok_or_else(|| Error::NotFound(format!("split {port} {location} not found").into()))?;
+    splitter
+        .set_weights(&weights)
+        .map_err(|err| Error::BadRequest(err.to_string().into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handle_admin_works() {
+        let admin = SettingAdmin {
+            listen: "127.0.0.1:9900".into(),
+            token: "secret".into(),
+        };
+        let host: SettingHost = toml::from_str(
+            r#"
+ip = "0.0.0.0"
+port = 4000
+route = []
+"#,
+        )
+        .unwrap();
+        let hosts = [host];
+        let req = |token: &str, path: &str| {
+            Request::builder()
+                .uri(path)
+                .header("Authorization", format!("Bearer {token}"))
+                .body(())
+                .unwrap()
+        };
+
+        let res = handle_admin(&admin, &hosts, &req("wrong", "/hosts"));
+        assert_eq!(res.status(), 401);
+        assert_eq!(res.headers()["WWW-Authenticate"], "Bearer");
+        let res = handle_admin(&admin, &hosts, &Request::get("/hosts").body(()).unwrap());
+        assert_eq!(res.status(), 401);
+        let res = handle_admin(&admin, &hosts, &req("secret", "/unknown"));
+        assert_eq!(res.status(), 404);
+        let res = handle_admin(&admin, &hosts, &req("secret", "/hosts"));
+        assert_eq!(res.status(), 200);
+        let body = futures_util::FutureExt::now_or_never(res.into_body().collect())
+            .unwrap()
+            .unwrap()
+            .to_bytes();
+        assert_eq!(
+            body,
+            r#"[{"ip":"0.0.0.0","port":4000,"routes":[],"filters":[],"limit_conn_rejected":0,"splits":[],"maintenance":false}]"#
+        );
+    }
+
+    #[test]
+    fn set_maintenance_works() {
+        let admin = SettingAdmin {
+            listen: "127.0.0.1:9900".into(),
+            token: "secret".into(),
+        };
+        let mut host: SettingHost = toml::from_str(
+            r#"
+ip = "0.0.0.0"
+port = 4000
+route = []
+"#,
+        )
+        .unwrap();
+        let route: crate::config::SettingRoute = toml::from_str(
+            r#"
+location = "/api/"
+proxy_pass = "http://localhost:3000"
+"#,
+        )
+        .unwrap();
+        host.route_map.insert("/api/".into(), route);
+        let hosts = [host];
+        let req = |method: Method, path: &str| {
+            Request::builder()
+                .method(method)
+                .uri(path)
+                .header("Authorization", "Bearer secret")
+                .body(())
+                .unwrap()
+        };
+        let body = |res: Response<CandyBody<Bytes>>| {
+            futures_util::FutureExt::now_or_never(res.into_body().collect())
+                .unwrap()
+                .unwrap()
+                .to_bytes()
+        };
+
+        let res = handle_admin(
+            &admin,
+            &hosts,
+            &req(Method::POST, "/hosts/4000/maintenance?enabled=true"),
+        );
+        assert_eq!(res.status(), 200);
+        assert!(hosts[0].maintenance.load(Ordering::Relaxed));
+        let res = handle_admin(
+            &admin,
+            &hosts,
+            &req(Method::POST, "/hosts/4001/maintenance?enabled=true"),
+        );
+        assert_eq!(res.status(), 404);
+        let res = handle_admin(
+            &admin,
+            &hosts,
+            &req(Method::POST, "/hosts/4000/maintenance"),
+        );
+        assert_eq!(res.status(), 400);
+        let res = handle_admin(
+            &admin,
+            &hosts,
+            &req(Method::POST, "/hosts/4000/maintenance?enabled=false"),
+        );
+        assert_eq!(res.status(), 200);
+        assert!(!hosts[0].maintenance.load(Ordering::Relaxed));
+
+        let res = handle_admin(&admin, &hosts, &req(Method::GET, "/upstreams"));
+        assert_eq!(
+            body(res),
+            r#"[{"port":4000,"location":"/api/","proxy_pass":"http://localhost:3000","buckets":[]}]"#
+        );
+    }
+
+    #[test]
+    fn set_split_weights_works() {
+        let mut host: SettingHost = toml::from_str(
+            r#"
+ip = "0.0.0.0"
+port = 4000
+route = []
+"#,
+        )
+        .unwrap();
+        let mut route: crate::config::SettingRoute = toml::from_str(
+            r#"
+location = "/api/"
+split = [{ upstream = "http://stable", weight = 95 }, { upstream = "http://canary", weight = 5 }]
+"#,
+        )
+        .unwrap();
+        let split = route.split.as_deref().unwrap();
+        route.splitter = Some(crate::http::split::Splitter::new(split, None).unwrap());
+        host.route_map.insert("/api/".into(), route);
+        let hosts = [host];
+
+        assert!(set_split_weights(&hosts, Some("port=4000&location=/api/&weights=0,100")).is_ok());
+        let splitter = hosts[0].route_map["/api/"].splitter.as_ref().unwrap();
+        assert_eq!(splitter.choose(&Default::default()), "http://canary");
+        assert!(set_split_weights(&hosts, Some("port=4000&location=/api/&weights=1")).is_err());
+        assert!(set_split_weights(&hosts, Some("port=4000&location=/api/&weights=0,0")).is_err());
+        assert!(set_split_weights(&hosts, Some("port=4000&location=/&weights=1,1")).is_err());
+        assert!(set_split_weights(&hosts, Some("port=4000&location=/api/")).is_err());
+
+        // values are percent-decoded
+        let query = "port=4000&location=%2Fapi%2F&weights=100%2C0";
+        assert!(set_split_weights(&hosts, Some(query)).is_ok());
+        assert_eq!(splitter.choose(&Default::default()), "http://stable");
+    }
+
+    #[tokio::test]
+    async fn mk_server_requires_token() {
+        use crate::service::tests::{init_settings, send_raw};
+
+        init_settings();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let admin: &'static SettingAdmin = Box::leak(Box::new(SettingAdmin {
+            listen: addr.to_string(),
+            token: "secret".into(),
+        }));
+        let shutdown = Shutdown::default();
+        let server = tokio::spawn(admin.mk_server(listener, shutdown.clone()));
+
+        let get = |auth: &'static str| async move {
+            let req = format!(
+                "GET /hosts HTTP/1.1\r\nHost: localhost\r\n{auth}Connection: close\r\n\r\n"
+            );
+            send_raw(addr, &req).await
+        };
+
+        let res = get("").await;
+        assert!(res.starts_with("HTTP/1.1 401 Unauthorized"));
+        assert!(res.contains("www-authenticate: Bearer\r\n"));
+        let res = get("Authorization: Bearer wrong\r\n").await;
+        assert!(res.starts_with("HTTP/1.1 401 Unauthorized"));
+        let res = get("Authorization: Bearer secret\r\n").await;
+        assert!(res.starts_with("HTTP/1.1 200 OK"));
+        assert!(res.contains("content-type: application/json\r\n"));
+
+        shutdown.terminate.cancel();
+        server.await.unwrap().unwrap();
+    }
+}
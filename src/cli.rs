@@ -7,4 +7,11 @@ pub struct Cli {
     /// Set a custom config file location.
     #[arg(short, long, value_name = "FILE", default_value = "./config.toml")]
     pub config: String,
+    /// Set log filter, like `debug` or `candy=debug,hyper=warn`.
+    /// Overrides `CANDY_LOG` env and `log_level` in config file.
+    #[arg(short, long, value_name = "FILTER")]
+    pub log_level: Option<String>,
+    /// Load config and register routes without binding ports.
+    #[arg(long)]
+    pub dry_run: bool,
 }
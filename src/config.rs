@@ -1,14 +1,29 @@
 use crate::{
     consts::{
-        host_index, insert_default_mimes, mime_default, timeout_default, types_default,
+        client_body_buffer_size_default, client_body_timeout_default,
+        compress_exclude_types_default, compress_prefer_default, expose_version_default,
+        filter_status_default, host_index, insert_default_mimes, invalid_referer_status_default,
+        limit_conn_status_default, merge_slashes_default, mime_default, proxy_strip_prefix_default,
+        shutdown_timeout_default, timeout_default, types_default, upstream_read_timeout_default,
         upstream_timeout_default,
     },
     error::Result,
+    http::{filter::RequestFilter, headers_file::HeadersFile, split::Splitter, vfs::EMBED_PREFIX},
+    utils::{compress::CompressType, limit::ConnLimiter, parse_assets_path},
+};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{atomic::AtomicBool, Arc},
 };
-use std::{borrow::Cow, collections::BTreeMap, fs};
 
-use anyhow::Context;
-use serde::Deserialize;
+use anyhow::{anyhow, Context};
+use http::{HeaderName, HeaderValue};
+use serde::{Deserialize, Deserializer};
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct ErrorRoute {
@@ -16,31 +31,332 @@ pub struct ErrorRoute {
     pub page: String,
 }
 
+/// Literal response from config
+/// Used for health checks, robots.txt and redirects without any file on disk
+#[derive(Deserialize, Clone, Debug)]
+pub struct ReturnRoute {
+    /// Response status code, 100-599
+    pub status: u16,
+    /// Response body, support `$variable` expansion
+    pub body: Option<String>,
+    /// Response content type
+    pub content_type: Option<String>,
+    /// `Location` header for redirects, support `$variable` expansion
+    pub location: Option<String>,
+}
+
+/// ETag generate mode for static files
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EtagMode {
+    /// Based on file last modified time and size
+    #[default]
+    Weak,
+    /// Based on partial file content hash, fall back to weak for large files
+    Strong,
+}
+
+/// How to read client request body before forward to upstream
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestBuffering {
+    /// Read whole body into memory
+    #[default]
+    Memory,
+    /// Spool body larger than `client_body_buffer_size` to temp file
+    Disk,
+    /// Stream body to upstream directly, redirects can not be followed
+    Off,
+}
+
+/// Upstream bucket of weighted traffic split
+#[derive(Deserialize, Clone, Debug)]
+pub struct SettingSplit {
+    /// Upstream url like `proxy_pass`
+    pub upstream: String,
+    pub weight: u32,
+}
+
+/// Response of static file request with invalid `Referer`
+#[derive(Deserialize, Clone, Debug)]
+pub struct InvalidRefererAction {
+    #[serde(default = "invalid_referer_status_default")]
+    pub status: u16,
+    /// Redirect to this url instead, like a watermark image
+    pub redirect: Option<String>,
+}
+
 /// Route in virtual host
-/// Can be a static file or a reverse proxy
+/// Can be a static file, a reverse proxy or a literal response
 #[derive(Deserialize, Clone, Debug)]
 pub struct SettingRoute {
     /// The register route
     pub location: String,
     /// The static assets root folder
     pub root: Option<String>,
-    /// Index files format
+    /// Index files of directory by priority, can be relative path like `subdir/index.html`
+    /// An empty list means no index file
     #[serde(default = "host_index")]
     pub index: Vec<String>,
     /// Custom error page
     pub error_page: Option<ErrorRoute>,
+    /// ETag generate mode
+    #[serde(default)]
+    pub etag_mode: EtagMode,
+    /// Serve `basename.{lang}.{ext}` or `basename.{ext}` variant of file
+    /// by `Accept-Language` and `Accept` header
+    #[serde(default)]
+    pub content_negotiation: bool,
+    /// Allowed `Referer` of static files, like `["none", "blocked", "server_names", "*.example.com"]`
+    pub valid_referers: Option<Vec<String>>,
+    /// Response of request with invalid `Referer`, default is 403
+    pub invalid_referer_action: Option<InvalidRefererAction>,
+    /// File extensions checked by `valid_referers`, like `["jpg", "png"]`
+    /// An empty list means all files
+    #[serde(default)]
+    pub protect_extensions: Vec<String>,
+    /// Deny clients from these ISO country codes, like `["RU", "KP"]`
+    /// Requires `[geoip]` and the `geoip` feature
+    #[serde(default)]
+    pub deny_countries: Vec<String>,
+    /// Netlify style `_headers` file of response headers by request path,
+    /// like `./public/_headers`, overrides host headers
+    pub headers_file: Option<String>,
+    /// Compiled `headers_file`
+    #[serde(skip_deserializing, skip_serializing)]
+    pub header_rules: Option<HeadersFile>,
 
     /// Reverse proxy url
     pub proxy_pass: Option<String>,
-    /// Timeout for connect to upstream
+    /// Split traffic between upstreams by weight, takes precedence over `proxy_pass`
+    pub split: Option<Vec<SettingSplit>>,
+    /// Hash `cookie:Name` or `header:Name` to stick a client to one upstream,
+    /// choose randomly when not set or the request has no such value
+    pub split_key: Option<String>,
+    /// Compiled traffic split
+    #[serde(skip_deserializing, skip_serializing)]
+    pub splitter: Option<Splitter>,
+    /// Timeout for the whole upstream request until response headers arrive, like `5s`,
+    /// also the connect timeout when `proxy_connect_timeout_ms` is not set
     #[serde(default = "upstream_timeout_default")]
-    pub proxy_timeout: u16,
+    pub proxy_timeout: HumanDuration,
+    /// Timeout for connect to upstream in milliseconds, overrides `proxy_timeout`
+    pub proxy_connect_timeout_ms: Option<u64>,
+    /// Timeout for receive response from upstream in milliseconds,
+    /// applies to response headers and again to each read of the response body
+    #[serde(default = "upstream_read_timeout_default")]
+    pub proxy_read_timeout_ms: u64,
+    /// Remove route location from request path before forward to upstream
+    #[serde(default = "proxy_strip_prefix_default")]
+    pub proxy_strip_prefix: bool,
+    /// Replace route location with this prefix before forward to upstream
+    pub proxy_rewrite_prefix: Option<String>,
+    /// How to read client request body before forward to upstream
+    #[serde(default)]
+    pub proxy_request_buffering: RequestBuffering,
+    /// Max request body size kept in memory in disk buffering mode
+    #[serde(default = "client_body_buffer_size_default")]
+    pub client_body_buffer_size: ByteSize,
+    /// Max request body size, larger body is rejected with 413, default is unlimited
+    pub client_max_body_size: Option<ByteSize>,
+    /// Directory of request body temp files, default is system temp directory
+    pub client_body_temp_path: Option<String>,
+    /// Compress upstream response with gzip when upstream doesn't compress
+    #[serde(default)]
+    pub proxy_compress: bool,
+    /// `preserve`, `upstream` or `custom:api.internal`, the `Host` header sent to upstream
+    /// Default is `upstream`, the proxy always sent the upstream host before this option
+    #[serde(default)]
+    pub proxy_host_header: ProxyHostHeader,
+
+    /// Limit response body rate in bytes per second, like `500k`, `0` disables the limit
+    pub limit_rate: Option<ByteSize>,
+    /// Bytes sent without rate limit, like `1m`
+    #[serde(default)]
+    pub limit_rate_after: ByteSize,
+
+    /// Literal response
+    #[serde(rename = "return")]
+    pub return_route: Option<ReturnRoute>,
 }
 
 /// Host routes
 /// Each host can have multiple routes
 pub type HostRouteMap = BTreeMap<String, SettingRoute>;
 
+/// Cookie set by virtual host
+/// Will be formatted to `Set-Cookie` header
+#[derive(Deserialize, Clone, Debug)]
+pub struct SettingCookie {
+    pub name: String,
+    pub value: String,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    /// Cookie max age in seconds
+    pub max_age: Option<i64>,
+    #[serde(default)]
+    pub secure: bool,
+    #[serde(default)]
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+}
+
+/// `SameSite` attribute of cookie
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    /// Attribute value in `Set-Cookie` header
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// Action of matched request filter
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    /// Response with filter status and stop handling
+    Deny,
+    /// Only emit a warning log
+    Log,
+}
+
+/// Request filter rule evaluated before routing
+#[derive(Deserialize, Clone, Debug)]
+pub struct SettingFilter {
+    /// Rule id in logs, default is the rule index
+    pub id: Option<String>,
+    /// `uri`, `query` or `header:Name`
+    #[serde(rename = "match")]
+    pub target: String,
+    /// Regex match the target value
+    pub regex: Option<String>,
+    /// Substring match the target value
+    pub contains: Option<String>,
+    pub action: FilterAction,
+    /// Response status for deny action, 444 means drop the connection
+    #[serde(default = "filter_status_default")]
+    pub status: u16,
+}
+
+/// Key to count in-flight requests
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LimitConnKey {
+    /// Client ip address
+    Ip,
+}
+
+/// Limit in-flight requests of each client
+#[derive(Deserialize, Clone, Debug)]
+pub struct SettingLimitConn {
+    pub key: LimitConnKey,
+    /// Max in-flight requests of one client, at least 1
+    pub max: usize,
+    /// Response status of rejected request, 400-599
+    #[serde(default = "limit_conn_status_default")]
+    pub status: u16,
+}
+
+/// MaxMind database, requires the `geoip` feature
+#[derive(Deserialize, Clone, Debug)]
+pub struct SettingGeoip {
+    /// Path of country database like `GeoLite2-Country.mmdb`
+    pub database: String,
+}
+
+/// How to send the `Server` header
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "String")]
+pub enum ServerTokens {
+    /// Add `Server: candy`, keep the one sent by upstream
+    On,
+    /// Remove `Server` and `X-Candy-Version`, including the one sent by upstream
+    Off,
+    /// Replace `Server` with this value, including the one sent by upstream
+    Custom(HeaderValue),
+}
+
+impl TryFrom<String> for ServerTokens {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "on" => Ok(Self::On),
+            "off" => Ok(Self::Off),
+            _ => match value.strip_prefix("custom:") {
+                Some(name) if !name.is_empty() => HeaderValue::from_str(name)
+                    .map(Self::Custom)
+                    .map_err(|err| format!("invalid server_tokens {value:?} {err}")),
+                _ => Err(format!(
+                    "invalid server_tokens {value:?}, expect on, off or custom:Name"
+                )),
+            },
+        }
+    }
+}
+
+/// `Host` header sent to upstream
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(try_from = "String")]
+pub enum ProxyHostHeader {
+    /// Keep the `Host` sent by client
+    Preserve,
+    /// Host and port of `proxy_pass`, or redirect target when following redirects,
+    /// the port is kept when `proxy_pass` has one, like `internal-svc:8080`
+    #[default]
+    Upstream,
+    /// Send this value
+    Custom(String),
+}
+
+impl TryFrom<String> for ProxyHostHeader {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "preserve" => Ok(Self::Preserve),
+            "upstream" => Ok(Self::Upstream),
+            _ => match value.strip_prefix("custom:") {
+                Some(host) if !host.is_empty() => Ok(Self::Custom(host.to_string())),
+                _ => Err(format!(
+                    "invalid proxy_host_header {value:?}, expect preserve, upstream or custom:host"
+                )),
+            },
+        }
+    }
+}
+
+/// Error response body format
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorFormat {
+    Json,
+    Text,
+    Html,
+}
+
+/// Log output backend
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogBackend {
+    #[default]
+    Stdout,
+    /// Append to `log_file` without ANSI colors
+    File,
+    /// systemd journal with syslog priorities, requires the `journald` feature
+    Journald,
+}
+
 /// Virtual host
 /// Each host can listen on one port and one ip
 #[derive(Deserialize, Clone, Debug)]
@@ -50,15 +366,86 @@ pub struct SettingHost {
     /// Host port
     pub port: u32,
     route: Vec<Option<SettingRoute>>,
+    /// Glob patterns of files contain more `[[route]]` entries for this host
+    /// Relative patterns are resolved from the directory of the including file
+    #[serde(default)]
+    includes: Vec<String>,
     /// Host route map
     #[serde(skip_deserializing, skip_serializing)]
     pub route_map: HostRouteMap,
-    /// HTTP keep-alive timeout
+    /// Route used when no location matches, can be any route type
+    /// The location can be omitted
+    #[serde(default, deserialize_with = "deserialize_fallback")]
+    pub fallback: Option<SettingRoute>,
+    /// Time to receive HTTP/1 request headers once the request started, like `75s`,
+    /// slow clients are disconnected
     #[serde(default = "timeout_default")]
-    pub timeout: u16,
+    pub timeout: HumanDuration,
+    /// Time to read whole request body before forward to upstream, like `60s`,
+    /// client gets 408 when exceeded, streamed body is not limited
+    #[serde(default = "client_body_timeout_default")]
+    pub client_body_timeout: HumanDuration,
+    /// Time to wait in-flight requests after SIGTERM or SIGINT, like `30s`
+    #[serde(default = "shutdown_timeout_default")]
+    pub shutdown_timeout: HumanDuration,
+    /// Merge consecutive slashes of request path, like `//assets///app.js`
+    /// Dot segments are always removed
+    #[serde(default = "merge_slashes_default")]
+    pub merge_slashes: bool,
+    /// Keep accepting connections after shutdown begins, like `5s`
+    /// The health endpoint answers 503 meanwhile so load balancers stop sending traffic
+    #[serde(default)]
+    pub shutdown_delay: HumanDuration,
+    /// Built-in health check path, like `/healthz`, answered before request filters
+    pub health_endpoint: Option<String>,
+    /// Write health check requests to access log
+    #[serde(default)]
+    pub health_access_log: bool,
+    /// Set when graceful shutdown has begun
+    #[serde(skip_deserializing, skip_serializing)]
+    pub draining: Arc<AtomicBool>,
+    /// Set from admin API, every request gets 503 except the health endpoint
+    #[serde(skip_deserializing, skip_serializing)]
+    pub maintenance: Arc<AtomicBool>,
     /// HTTP headers
     /// Used to overwrite headers in config
     pub headers: Option<BTreeMap<String, String>>,
+    /// `on`, `off` or `custom:Name`, controls `Server` and `X-Candy-Version` headers
+    /// including the `Server` header sent by upstream,
+    /// legacy `server_header` and `hide_server_header` are merged into it on load
+    pub server_tokens: Option<ServerTokens>,
+    /// Replace the `Server` header value, same as `server_tokens = "custom:Name"`
+    pub server_header: Option<String>,
+    /// Do not add `Server` and `X-Candy-Version` headers, same as `server_tokens = "off"`
+    #[serde(default)]
+    pub hide_server_header: bool,
+    /// Add `X-Candy-Version` header
+    #[serde(default = "expose_version_default")]
+    pub expose_version: bool,
+    /// Cookies add to every response
+    pub cookies: Option<Vec<SettingCookie>>,
+    /// Request filter rules evaluated by order before routing
+    pub request_filters: Option<Vec<SettingFilter>>,
+    /// Compiled request filter rules
+    #[serde(skip_deserializing, skip_serializing)]
+    pub filters: Vec<RequestFilter>,
+    /// Limit in-flight requests of each client
+    pub limit_conn: Option<SettingLimitConn>,
+    /// In-flight requests counter
+    #[serde(skip_deserializing, skip_serializing)]
+    pub conn_limiter: ConnLimiter,
+    /// Error response format, decided by client `Accept` header when not set
+    pub error_format: Option<ErrorFormat>,
+}
+
+/// Admin API listener
+/// Only listen on loopback address
+#[derive(Deserialize, Clone, Debug)]
+pub struct SettingAdmin {
+    /// Listen address, like `127.0.0.1:9900`
+    pub listen: String,
+    /// Bearer token required by every admin request
+    pub token: String,
 }
 
 pub type MIMEType = BTreeMap<Cow<'static, str>, Cow<'static, str>>;
@@ -66,6 +453,14 @@ pub type MIMEType = BTreeMap<Cow<'static, str>, Cow<'static, str>>;
 /// Whole config settings
 #[derive(Deserialize, Clone, Debug)]
 pub struct Settings {
+    /// Log filter, like `info` or `candy=debug,hyper=warn`
+    /// `CANDY_LOG` env and `--log-level` flag take precedence
+    pub log_level: Option<String>,
+    /// Where logs are written, `stdout` by default
+    #[serde(default)]
+    pub log_backend: LogBackend,
+    /// Log file path, required by `log_backend = "file"`
+    pub log_file: Option<String>,
     /// Default file type for unknow file
     #[serde(default = "mime_default")]
     pub default_type: Cow<'static, str>,
@@ -74,26 +469,967 @@ pub struct Settings {
     pub types: MIMEType,
     /// Virtual host
     pub host: Vec<SettingHost>,
+    /// Admin API
+    pub admin: Option<SettingAdmin>,
+    /// Deprecated values and missing paths found in config
+    #[serde(skip_deserializing, skip_serializing)]
+    pub warnings: Vec<String>,
+    /// Resolve client country from MaxMind database
+    pub geoip: Option<SettingGeoip>,
+    /// Refuse to start when referenced paths are missing or unreadable,
+    /// otherwise they are reported as warnings
+    #[serde(default)]
+    pub strict_paths: bool,
+    /// Response compression of static files and `proxy_compress` routes
+    #[serde(default)]
+    pub compression: SettingCompression,
+}
+
+/// Response compression
+#[derive(Deserialize, Clone, Debug)]
+pub struct SettingCompression {
+    /// Content types never compressed, like `image/` or `text/event-stream`
+    #[serde(default = "compress_exclude_types_default")]
+    pub exclude_types: Vec<String>,
+    /// Enabled encodings by preference when client accepts several with the same quality
+    #[serde(default = "compress_prefer_default")]
+    pub prefer: Vec<CompressType>,
+}
+
+impl Default for SettingCompression {
+    fn default() -> Self {
+        Self {
+            exclude_types: compress_exclude_types_default(),
+            prefer: compress_prefer_default(),
+        }
+    }
 }
 
 impl Settings {
     pub fn new(path: &str) -> Result<Self> {
+        // drop warnings left by a previous call that returned early
+        DEPRECATIONS.take();
         let file = fs::read_to_string(path).with_context(|| format!("read {path} failed"))?;
         let mut settings: Settings = toml::from_str(&file)?;
+        if settings
+            .admin
+            .as_ref()
+            .is_some_and(|admin| admin.token.is_empty())
+        {
+            return Err(anyhow!("admin token is empty").into());
+        }
 
         // convert route map
-        settings.host.iter_mut().for_each(|host| {
-            host.route
+        let config_path = fs::canonicalize(path)?;
+        let base = config_path.parent().unwrap_or(Path::new("."));
+        for host in settings.host.iter_mut() {
+            let mut stack = vec![config_path.clone()];
+            let includes = load_includes(&host.includes, base, &mut stack)?;
+            let routes = host
+                .route
                 .iter_mut()
                 .filter_map(Option::take)
-                .for_each(|route| {
-                    host.route_map.insert(route.location.to_string(), route);
-                });
-        });
+                .chain(includes)
+                .collect::<Vec<_>>();
+            let conflicts = check_routes(&routes)
+                .with_context(|| format!("host {}:{} invalid routes", host.ip, host.port))?;
+            // trailing slash conflicts loaded before, keep them as warnings for one release
+            for conflict in conflicts {
+                deprecated(format!(
+                    "host {}:{} {conflict}, this will be an error in the next release",
+                    host.ip, host.port
+                ));
+            }
+            routes.into_iter().for_each(|route| {
+                host.route_map.insert(route.location.to_string(), route);
+            });
+            if let Some(fallback) = &host.fallback {
+                check_routes(std::slice::from_ref(fallback))
+                    .with_context(|| format!("host {}:{} invalid fallback", host.ip, host.port))?;
+            }
+            // compile traffic splits and headers files
+            for route in host.route_map.values_mut().chain(host.fallback.as_mut()) {
+                // country lookup without database would let every client through
+                if !route.deny_countries.is_empty()
+                    && (!cfg!(feature = "geoip") || settings.geoip.is_none())
+                {
+                    return Err(anyhow!(
+                        "host {}:{} route {:?} deny_countries requires [geoip] and the `geoip` feature",
+                        host.ip,
+                        host.port,
+                        route.location
+                    )
+                    .into());
+                }
+                if !cfg!(feature = "embed")
+                    && route
+                        .root
+                        .as_deref()
+                        .is_some_and(|root| root.starts_with(EMBED_PREFIX))
+                {
+                    return Err(anyhow!(
+                        "host {}:{} route {:?} embedded root requires the `embed` feature",
+                        host.ip,
+                        host.port,
+                        route.location
+                    )
+                    .into());
+                }
+                if let Some(ret) = &route.return_route {
+                    check_return(ret).with_context(|| {
+                        format!(
+                            "host {}:{} route {:?} invalid return",
+                            host.ip, host.port, route.location
+                        )
+                    })?;
+                }
+                if let Some(split) = &route.split {
+                    route.splitter = Some(Splitter::new(split, route.split_key.as_deref())?);
+                }
+                if let Some(headers_file) = &route.headers_file {
+                    route.header_rules = Some(HeadersFile::load(headers_file)?);
+                }
+            }
+            // add_headers builds these per request, invalid ones would drop all of them
+            for (name, value) in host.headers.iter().flatten() {
+                let invalid = || format!("host {}:{} invalid header {name}", host.ip, host.port);
+                HeaderName::from_bytes(name.as_bytes()).with_context(invalid)?;
+                HeaderValue::from_str(value).with_context(invalid)?;
+            }
+            if let Some(limit) = &host.limit_conn {
+                if limit.max == 0 || !(400..=599).contains(&limit.status) {
+                    return Err(anyhow!(
+                        "host {}:{} invalid limit_conn, expect max >= 1 and status 400-599",
+                        host.ip,
+                        host.port
+                    )
+                    .into());
+                }
+            }
+            for cookie in host.cookies.iter().flatten() {
+                check_cookie(cookie)
+                    .with_context(|| format!("host {}:{} invalid cookie", host.ip, host.port))?;
+            }
+            host.server_tokens = Some(
+                resolve_server_tokens(host)
+                    .with_context(|| format!("host {}:{} invalid", host.ip, host.port))?,
+            );
+            // compile request filters
+            if let Some(filters) = &host.request_filters {
+                host.filters = filters
+                    .iter()
+                    .enumerate()
+                    .map(|(index, filter)| RequestFilter::new(index, filter))
+                    .collect::<Result<_>>()?;
+            }
+        }
+
+        // preflight referenced paths
+        let problems = settings.check_paths();
+        if settings.strict_paths && !problems.is_empty() {
+            return Err(anyhow!("strict_paths check failed: {}", problems.join("; ")).into());
+        }
 
         // combine mime types
         insert_default_mimes(&mut settings.types);
+        settings.warnings = DEPRECATIONS.take();
+        settings.warnings.extend(problems);
 
         Ok(settings)
     }
+
+    /// Stat every path referenced by config before serving,
+    /// like route `root`, `error_page`, `client_body_temp_path` and geoip database
+    ///
+    /// ## Return
+    ///
+    /// description of each missing or unreadable path
+    pub fn check_paths(&self) -> Vec<String> {
+        let mut problems = vec![];
+        let mut check = |name: String, path: &str, dir: bool| -> bool {
+            let err = match fs::metadata(path) {
+                Ok(meta) if meta.is_dir() != dir => {
+                    let kind = if dir { "a directory" } else { "a file" };
+                    format!("is not {kind}")
+                }
+                Ok(_) if dir => match fs::read_dir(path) {
+                    Ok(_) => return true,
+                    Err(err) => format!("is unreadable {err}"),
+                },
+                Ok(_) => return true,
+                Err(err) => err.to_string(),
+            };
+            problems.push(format!("{name} {path} {err}"));
+            false
+        };
+
+        for host in &self.host {
+            for route in host.route_map.values().chain(host.fallback.as_ref()) {
+                let name = format!("host {}:{} route {:?}", host.ip, host.port, route.location);
+                if let Some(temp_path) = &route.client_body_temp_path {
+                    check(format!("{name} client_body_temp_path"), temp_path, true);
+                }
+                let Some(root) = route.root.as_deref() else {
+                    continue;
+                };
+                // embedded files are checked at compile time
+                if root.starts_with(EMBED_PREFIX) {
+                    continue;
+                }
+                if !check(format!("{name} root"), root, true) {
+                    continue;
+                }
+                if let Some(error_page) = &route.error_page {
+                    let page = parse_assets_path("", root, &error_page.page);
+                    check(format!("{name} error_page"), &page, false);
+                }
+            }
+        }
+        if let Some(geoip) = &self.geoip {
+            check("geoip database".into(), &geoip.database, false);
+        }
+        problems
+    }
+}
+
+/// Number or string value in config
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UnitValue {
+    Integer(i64),
+    Float(f64),
+    Text(String),
+}
+
+/// Split number and unit suffix like `1.5GB` or `30s`
+///
+/// ## Return
+///
+/// none when the number is invalid or negative
+fn split_unit(value: &str) -> Option<(f64, String)> {
+    let value = value.trim();
+    let index = value
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(index);
+    let number = number.trim();
+    if number.is_empty() || number.starts_with(['-', '+']) {
+        return None;
+    }
+    let number = number.parse::<f64>().ok().filter(|n| n.is_finite())?;
+    Some((number, unit.to_ascii_lowercase()))
+}
+
+/// Byte size in config, plain bytes number or string with suffix
+/// like `512k`, `10MB` or `1.5g`, units are 1024 based like nginx
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+    type Err = anyhow::Error;
+
+    fn from_str(size: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow!("invalid size {size:?}");
+        let (number, unit) = split_unit(size).ok_or_else(invalid)?;
+        let unit: u64 = match unit.as_str() {
+            "" | "b" => 1,
+            "k" | "kb" | "kib" => 1 << 10,
+            "m" | "mb" | "mib" => 1 << 20,
+            "g" | "gb" | "gib" => 1 << 30,
+            _ => return Err(invalid()),
+        };
+        let bytes = number * unit as f64;
+        if bytes >= u64::MAX as f64 {
+            return Err(invalid());
+        }
+        Ok(Self(bytes as u64))
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match UnitValue::deserialize(deserializer)? {
+            UnitValue::Integer(bytes) => u64::try_from(bytes)
+                .map(Self)
+                .map_err(|_| serde::de::Error::custom(format!("invalid size {bytes}"))),
+            UnitValue::Float(bytes) => Err(serde::de::Error::custom(format!(
+                "invalid size {bytes}, use a string like \"1.5m\""
+            ))),
+            UnitValue::Text(text) => text.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// Duration in config, string with suffix like `500ms`, `30s`, `5m`, `1h` or `1d`
+/// Plain numbers are seconds for backward compatibility, but deprecated
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HumanDuration(pub std::time::Duration);
+
+impl HumanDuration {
+    pub const fn from_secs(secs: u64) -> Self {
+        Self(std::time::Duration::from_secs(secs))
+    }
+}
+
+impl FromStr for HumanDuration {
+    type Err = anyhow::Error;
+
+    fn from_str(duration: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow!("invalid duration {duration:?}");
+        let (number, unit) = split_unit(duration).ok_or_else(invalid)?;
+        let secs = match unit.as_str() {
+            "" => {
+                deprecated(format!(
+                    "unit-less duration {duration:?} is deprecated, use \"{number}s\""
+                ));
+                number
+            }
+            "ms" => number / 1000.0,
+            "s" => number,
+            "m" => number * 60.0,
+            "h" => number * 3600.0,
+            "d" => number * 86400.0,
+            _ => return Err(invalid()),
+        };
+        std::time::Duration::try_from_secs_f64(secs)
+            .map(Self)
+            .map_err(|_| invalid())
+    }
+}
+
+impl<'de> Deserialize<'de> for HumanDuration {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = match UnitValue::deserialize(deserializer)? {
+            UnitValue::Integer(secs) => secs.to_string(),
+            UnitValue::Float(secs) => secs.to_string(),
+            UnitValue::Text(text) => text,
+        };
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+thread_local! {
+    /// Deprecation warnings found while parsing config,
+    /// logged after the logger is initialized
+    static DEPRECATIONS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+fn deprecated(warning: String) {
+    DEPRECATIONS.with_borrow_mut(|deprecations| deprecations.push(warning));
+}
+
+/// Fallback route from config, location defaults to `/`
+fn deserialize_fallback<'de, D>(deserializer: D) -> Result<Option<SettingRoute>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut table = toml::Table::deserialize(deserializer)?;
+    table
+        .entry("location")
+        .or_insert_with(|| toml::Value::String("/".into()));
+    toml::Value::Table(table)
+        .try_into()
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Check routes of one host before any socket is bound
+///
+/// Each route must be exactly one of static file, reverse proxy or literal response,
+/// and a location must not be defined twice. Locations that only differ by trailing slash,
+/// like `/doc` and `/doc/`, are still accepted and returned as conflicts.
+///
+/// ## Arguments
+///
+/// `routes`: routes of host and its include files by order
+///
+/// ## Return
+///
+/// Conflicts of locations that only differ by trailing slash
+fn check_routes(routes: &[SettingRoute]) -> anyhow::Result<Vec<String>> {
+    let mut exact = BTreeMap::new();
+    let mut locations = BTreeMap::new();
+    let mut conflicts = vec![];
+    for (index, route) in routes.iter().enumerate() {
+        let kinds = [
+            ("root", route.root.is_some()),
+            (
+                "proxy_pass",
+                route.proxy_pass.is_some() || route.split.is_some(),
+            ),
+            ("return", route.return_route.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, set)| set.then_some(name))
+        .collect::<Vec<_>>();
+        if kinds.len() > 1 {
+            return Err(anyhow!(
+                "route #{index} {:?} sets more than one of {}",
+                route.location,
+                kinds.join(", ")
+            ));
+        }
+        if let Some(first) = exact.insert(route.location.as_str(), index) {
+            return Err(anyhow!(
+                "route #{first} {:?} is duplicated by route #{index}",
+                route.location
+            ));
+        }
+        let location = match route.location.trim_end_matches('/') {
+            "" => "/",
+            location => location,
+        };
+        if let Some((first, other)) = locations.insert(location, (index, &route.location)) {
+            conflicts.push(format!(
+                "route #{first} {other:?} conflicts with route #{index} {:?}",
+                route.location
+            ));
+        }
+    }
+    Ok(conflicts)
+}
+
+/// Check `return` directive, so it never fails with 500 at request time
+///
+/// ## Arguments
+///
+/// `ret`: return directive of route
+fn check_return(ret: &ReturnRoute) -> anyhow::Result<()> {
+    if !(100..=599).contains(&ret.status) {
+        return Err(anyhow!("invalid status {}, expect 100-599", ret.status));
+    }
+    if let Some(content_type) = &ret.content_type {
+        HeaderValue::from_str(content_type)
+            .with_context(|| format!("invalid content_type {content_type:?}"))?;
+    }
+    Ok(())
+}
+
+/// Check cookie from config follows `Set-Cookie` syntax, so the header never fails per request
+/// https://datatracker.ietf.org/doc/html/rfc6265#section-4.1.1
+///
+/// ## Arguments
+///
+/// `cookie`: cookie of virtual host
+fn check_cookie(cookie: &SettingCookie) -> anyhow::Result<()> {
+    // cookie-name is a token
+    let tchar = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    if cookie.name.is_empty() || !cookie.name.chars().all(tchar) {
+        return Err(anyhow!("invalid cookie name {:?}", cookie.name));
+    }
+    // cookie-value, optionally wrapped in double quotes
+    let value = cookie
+        .value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(&cookie.value);
+    let cookie_octet = |c: char| c.is_ascii_graphic() && !matches!(c, '"' | ',' | ';' | '\\');
+    if !value.chars().all(cookie_octet) {
+        return Err(anyhow!(
+            "invalid cookie {} value {:?}",
+            cookie.name,
+            cookie.value
+        ));
+    }
+    // av-octet, any char except CTLs and `;`
+    let av_octet = |c: char| (c == ' ' || c.is_ascii_graphic()) && c != ';';
+    if let Some(path) = cookie
+        .path
+        .as_ref()
+        .filter(|path| !path.chars().all(av_octet))
+    {
+        return Err(anyhow!("invalid cookie {} path {path:?}", cookie.name));
+    }
+    let domain_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '-');
+    if let Some(domain) = cookie
+        .domain
+        .as_ref()
+        .filter(|domain| domain.is_empty() || !domain.chars().all(domain_char))
+    {
+        return Err(anyhow!("invalid cookie {} domain {domain:?}", cookie.name));
+    }
+    Ok(())
+}
+
+/// Merge legacy `server_header` and `hide_server_header` into `server_tokens`,
+/// they can not be combined since it is unclear which one should win
+///
+/// ## Arguments
+///
+/// `host`: virtual host from config file
+fn resolve_server_tokens(host: &SettingHost) -> anyhow::Result<ServerTokens> {
+    let legacy = host.server_header.is_some() || host.hide_server_header;
+    match (&host.server_tokens, &host.server_header) {
+        (Some(_), _) if legacy => Err(anyhow!(
+            "server_tokens can not be combined with server_header or hide_server_header"
+        )),
+        (Some(tokens), _) => Ok(tokens.clone()),
+        (None, _) if host.hide_server_header => Ok(ServerTokens::Off),
+        (None, Some(server)) => Ok(ServerTokens::Custom(
+            HeaderValue::from_str(server)
+                .with_context(|| format!("invalid server_header {server:?}"))?,
+        )),
+        (None, None) => Ok(ServerTokens::On),
+    }
+}
+
+/// Routes split from virtual host by `includes`
+#[derive(Deserialize, Debug)]
+struct IncludeRoutes {
+    #[serde(default)]
+    route: Vec<SettingRoute>,
+    /// Included files can include other files
+    #[serde(default)]
+    includes: Vec<String>,
+}
+
+/// Load routes from include files recursively,
+/// files matched by one pattern are loaded by sorted filename
+///
+/// ## Arguments
+///
+/// `patterns`: glob patterns from `includes` field
+/// `base`: directory of the including file
+/// `stack`: files currently being included, used to detect circular includes
+fn load_includes(
+    patterns: &[String],
+    base: &Path,
+    stack: &mut Vec<PathBuf>,
+) -> Result<Vec<SettingRoute>> {
+    let mut routes = vec![];
+    for pattern in patterns {
+        let pattern = base.join(pattern);
+        let pattern = pattern
+            .to_str()
+            .ok_or(anyhow!("invalid include pattern {}", pattern.display()))?;
+        let mut paths = glob::glob(pattern)
+            .with_context(|| format!("invalid include pattern {pattern}"))?
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("read include {pattern} failed"))?;
+        paths.sort();
+        for path in paths {
+            let path = fs::canonicalize(&path)?;
+            if stack.contains(&path) {
+                return Err(anyhow!("circular include {}", path.display()).into());
+            }
+            let file = fs::read_to_string(&path)
+                .with_context(|| format!("read {} failed", path.display()))?;
+            let include: IncludeRoutes = toml::from_str(&file)?;
+            routes.extend(include.route);
+            let base = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+            stack.push(path);
+            routes.extend(load_includes(&include.includes, &base, stack)?);
+            stack.pop();
+        }
+    }
+    Ok(routes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_works() {
+        let parse_size = |size: &str| size.parse::<ByteSize>().ok().map(|size| size.0);
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("500k"), Some(500 * 1024));
+        assert_eq!(parse_size("1M"), Some(1024 * 1024));
+        assert_eq!(parse_size("2g"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("10MB"), Some(10 * 1024 * 1024));
+        assert_eq!(parse_size("1.5GB"), Some(1536 * 1024 * 1024));
+        assert_eq!(parse_size("0"), Some(0));
+        assert_eq!(parse_size("1x"), None);
+        assert_eq!(parse_size("k"), None);
+        assert_eq!(parse_size("-1k"), None);
+        assert_eq!(parse_size("99999999999999g"), None);
+
+        #[derive(Deserialize)]
+        struct Size {
+            size: ByteSize,
+        }
+        let size = |text: &str| toml::from_str::<Size>(text).map(|size| size.size.0);
+        assert_eq!(size("size = 16384").unwrap(), 16384);
+        assert_eq!(size("size = \"16k\"").unwrap(), 16384);
+        assert!(size("size = -1").is_err());
+        assert!(size("size = 1.5").is_err());
+    }
+
+    #[test]
+    fn log_backend_works() {
+        let settings: Settings = toml::from_str("host = []").unwrap();
+        assert_eq!(settings.log_backend, LogBackend::Stdout);
+        let settings: Settings =
+            toml::from_str("log_backend = \"file\"\nlog_file = \"candy.log\"\nhost = []").unwrap();
+        assert_eq!(settings.log_backend, LogBackend::File);
+        assert_eq!(settings.log_file.as_deref(), Some("candy.log"));
+        let settings: Settings = toml::from_str("log_backend = \"journald\"\nhost = []").unwrap();
+        assert_eq!(settings.log_backend, LogBackend::Journald);
+        assert!(toml::from_str::<Settings>("log_backend = \"syslog\"\nhost = []").is_err());
+    }
+
+    #[test]
+    fn parse_duration_works() {
+        let parse = |duration: &str| duration.parse::<HumanDuration>().ok().map(|d| d.0);
+        let secs = std::time::Duration::from_secs;
+        assert_eq!(parse("30s"), Some(secs(30)));
+        assert_eq!(parse("5m"), Some(secs(300)));
+        assert_eq!(parse("1h"), Some(secs(3600)));
+        assert_eq!(parse("500ms"), Some(std::time::Duration::from_millis(500)));
+        assert_eq!(parse("1.5s"), Some(std::time::Duration::from_millis(1500)));
+        assert_eq!(parse("0s"), Some(secs(0)));
+        assert_eq!(parse("-5s"), None);
+        assert_eq!(parse("5x"), None);
+        assert_eq!(parse("s"), None);
+
+        // plain numbers keep loading as seconds with a deprecation warning
+        #[derive(Deserialize)]
+        struct Timeout {
+            timeout: HumanDuration,
+        }
+        let timeout = |text: &str| toml::from_str::<Timeout>(text).map(|t| t.timeout.0);
+        assert_eq!(timeout("timeout = 15").unwrap(), secs(15));
+        assert_eq!(timeout("timeout = \"15s\"").unwrap(), secs(15));
+        assert!(timeout("timeout = -15").is_err());
+        let warnings = DEPRECATIONS.take();
+        assert!(warnings
+            .iter()
+            .any(|warning| warning.contains("unit-less duration \"15\"")));
+    }
+
+    #[test]
+    fn includes_works() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("sites")).unwrap();
+        let main = dir.join("config.toml");
+        fs::write(
+            &main,
+            r#"
+[[host]]
+ip = "0.0.0.0"
+port = 4000
+includes = ["./sites/*.toml"]
+
+[[host.route]]
+location = "/"
+root = "./html"
+"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("sites/a.toml"),
+            "[[route]]\nlocation = \"/a/\"\nroot = \"./a\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("sites/b.toml"),
+            "[[route]]\nlocation = \"/b/\"\nproxy_pass = \"http://localhost:3000\"\n",
+        )
+        .unwrap();
+        let settings = Settings::new(main.to_str().unwrap()).unwrap();
+        let routes = settings.host[0].route_map.keys().collect::<Vec<_>>();
+        assert_eq!(routes, vec!["/", "/a/", "/b/"]);
+
+        // b.toml includes the directory contains itself
+        fs::write(
+            dir.join("sites/b.toml"),
+            "includes = [\"*.toml\"]\n[[route]]\nlocation = \"/b/\"\nroot = \"./b\"\n",
+        )
+        .unwrap();
+        let err = Settings::new(main.to_str().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("circular include"));
+    }
+
+    /// Load config text by `Settings::new`
+    fn load(text: &str) -> Result<Settings> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, text).unwrap();
+        Settings::new(path.to_str().unwrap())
+    }
+
+    #[test]
+    fn settings_new_rejects_invalid() {
+        let err = load(
+            r#"
+host = []
+[admin]
+listen = "127.0.0.1:9900"
+token = ""
+"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("admin token is empty"));
+
+        // deny_countries never fails open without geoip database
+        let err = load(
+            r#"
+[[host]]
+ip = "127.0.0.1"
+port = 4000
+[[host.route]]
+location = "/"
+root = "./html"
+deny_countries = ["KP"]
+"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("deny_countries requires [geoip]"));
+
+        // embedded root never falls back to 500 at request time
+        let embed = load(
+            r#"
+[[host]]
+ip = "127.0.0.1"
+port = 4000
+[[host.route]]
+location = "/"
+root = "embed://site"
+"#,
+        );
+        assert_eq!(embed.is_err(), !cfg!(feature = "embed"));
+
+        // warnings of a failed load never leak into the next one
+        let err = load(
+            r#"
+[[host]]
+ip = "127.0.0.1"
+port = 4000
+timeout = 15
+route = []
+[admin]
+listen = "127.0.0.1:9900"
+token = ""
+"#,
+        );
+        assert!(err.is_err());
+        let settings = load("host = []").unwrap();
+        assert!(settings.warnings.is_empty(), "{:?}", settings.warnings);
+
+        // trailing slash conflicts still load for one release
+        let settings = load(
+            r#"
+[[host]]
+ip = "127.0.0.1"
+port = 4000
+[[host.route]]
+location = "/doc"
+return = { status = 204 }
+[[host.route]]
+location = "/doc/"
+return = { status = 204 }
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.warnings,
+            [
+                r#"host 127.0.0.1:4000 route #0 "/doc" conflicts with route #1 "/doc/", this will be an error in the next release"#
+            ]
+        );
+
+        let host = |options: &str| {
+            load(&format!(
+                "[[host]]\nip = \"127.0.0.1\"\nport = 4000\nroute = []\n{options}"
+            ))
+        };
+        let err = host("server_tokens = \"off\"\nserver_header = \"nginx\"").unwrap_err();
+        assert!(err.to_string().contains("invalid"));
+        assert!(host("server_tokens = \"on\"\nhide_server_header = true").is_err());
+        assert!(host("server_header = \"bad\\nvalue\"").is_err());
+    }
+
+    #[test]
+    fn check_return_works() {
+        let route = |ret: &str| {
+            load(&format!(
+                "[[host]]\nip = \"127.0.0.1\"\nport = 4000\n[[host.route]]\nlocation = \"/\"\nreturn = {{ {ret} }}"
+            ))
+        };
+        assert!(route("status = 200, body = \"ok\", content_type = \"text/plain\"").is_ok());
+        assert!(route("status = 302, location = \"https://$host/\"").is_ok());
+        let err = route("status = 1000").unwrap_err();
+        assert!(format!("{err:?}").contains("invalid status 1000"));
+        assert!(route("status = 99").is_err());
+        assert!(route("status = 200, content_type = \"text/plain\\n\"").is_err());
+    }
+
+    #[test]
+    fn check_cookie_works() {
+        let host = |cookie: &str| {
+            load(&format!(
+                "[[host]]\nip = \"127.0.0.1\"\nport = 4000\nroute = []\n[[host.cookies]]\n{cookie}"
+            ))
+        };
+        let settings = host(
+            "name = \"sid\"\nvalue = \"\\\"a1\\\"\"\npath = \"/app\"\ndomain = \"example.com\"\nsame_site = \"Strict\"",
+        )
+        .unwrap();
+        let cookies = settings.host[0].cookies.as_ref().unwrap();
+        assert_eq!(cookies[0].same_site, Some(SameSite::Strict));
+
+        for invalid in [
+            "name = \"a=b\"\nvalue = \"1\"",
+            "name = \"a b\"\nvalue = \"1\"",
+            "name = \"\"\nvalue = \"1\"",
+            "name = \"sid\"\nvalue = \"1; Domain=evil.com\"",
+            "name = \"sid\"\nvalue = \"a b\"",
+            "name = \"sid\"\nvalue = \"a\\u0001\"",
+            "name = \"sid\"\nvalue = \"1\"\npath = \"/;x\"",
+            "name = \"sid\"\nvalue = \"1\"\ndomain = \"a.com; Secure\"",
+            "name = \"sid\"\nvalue = \"1\"\nsame_site = \"Loose\"",
+        ] {
+            assert!(host(invalid).is_err(), "{invalid}");
+        }
+        let limit = |limit: &str| {
+            load(&format!(
+                "[[host]]\nip = \"127.0.0.1\"\nport = 4000\nroute = []\nlimit_conn = {{ key = \"ip\", {limit} }}"
+            ))
+        };
+        assert!(limit("max = 10, status = 429").is_ok());
+        assert!(limit("max = 0").is_err());
+        assert!(limit("max = 10, status = 1000").is_err());
+        assert!(limit("max = 10, status = 200").is_err());
+        let header = "[[host]]\nip = \"127.0.0.1\"\nport = 4000\nroute = []\n[host.headers]\n\"X Bad\" = \"1\"";
+        assert!(load(header).is_err());
+    }
+
+    #[test]
+    fn resolve_server_tokens_works() {
+        let tokens = |options: &str| {
+            let settings = load(&format!(
+                "[[host]]\nip = \"127.0.0.1\"\nport = 4000\nroute = []\n{options}"
+            ))
+            .unwrap();
+            settings.host[0].server_tokens.clone().unwrap()
+        };
+        assert_eq!(tokens(""), ServerTokens::On);
+        assert_eq!(tokens("server_tokens = \"off\""), ServerTokens::Off);
+        assert_eq!(tokens("hide_server_header = true"), ServerTokens::Off);
+        assert_eq!(
+            tokens("server_header = \"nginx\""),
+            ServerTokens::Custom(HeaderValue::from_static("nginx"))
+        );
+        // hide wins over replace, same as before server_tokens
+        assert_eq!(
+            tokens("server_header = \"nginx\"\nhide_server_header = true"),
+            ServerTokens::Off
+        );
+    }
+
+    #[test]
+    fn check_paths_works() {
+        let root = concat!(env!("CARGO_MANIFEST_DIR"), "/embed/site");
+        let settings: Settings = toml::from_str(&format!(
+            r#"
+[[host]]
+ip = "127.0.0.1"
+port = 4000
+fallback = {{ root = "{root}", error_page = {{ status = 404, page = "index.html" }} }}
+
+[[host.route]]
+location = "/"
+root = "{root}/missing"
+error_page = {{ status = 404, page = "404.html" }}
+
+[[host.route]]
+location = "/page/"
+root = "{root}/index.html"
+
+[[host.route]]
+location = "/upload/"
+proxy_pass = "http://127.0.0.1:3000"
+client_body_temp_path = "{root}"
+
+[[host.route]]
+location = "/embed/"
+root = "embed://site"
+"#
+        ))
+        .unwrap();
+        let mut settings = settings;
+        let host = &mut settings.host[0];
+        for route in host.route.iter_mut().filter_map(Option::take) {
+            host.route_map.insert(route.location.to_string(), route);
+        }
+        let problems = settings.check_paths();
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].starts_with(&format!(
+            "host 127.0.0.1:4000 route \"/\" root {root}/missing "
+        )));
+        assert_eq!(
+            problems[1],
+            format!(
+                "host 127.0.0.1:4000 route \"/page/\" root {root}/index.html is not a directory"
+            )
+        );
+    }
+
+    #[test]
+    fn check_routes_works() {
+        let routes = |text: &str| toml::from_str::<IncludeRoutes>(text).unwrap().route;
+        let ok = routes(
+            r#"
+[[route]]
+location = "/"
+root = "./html"
+[[route]]
+location = "/doc/"
+root = "./doc"
+[[route]]
+location = "/api/"
+proxy_pass = "http://localhost:3000"
+"#,
+        );
+        assert!(check_routes(&ok).unwrap().is_empty());
+
+        let conflict = routes(
+            r#"
+[[route]]
+location = "/doc"
+root = "./doc"
+[[route]]
+location = "/"
+root = "./html"
+[[route]]
+location = "/doc/"
+root = "./doc"
+"#,
+        );
+        assert_eq!(
+            check_routes(&conflict).unwrap(),
+            [r#"route #0 "/doc" conflicts with route #2 "/doc/""#]
+        );
+
+        let duplicated = routes(
+            r#"
+[[route]]
+location = "/doc/"
+root = "./doc"
+[[route]]
+location = "/doc/"
+root = "./html"
+"#,
+        );
+        let err = check_routes(&duplicated).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"route #0 "/doc/" is duplicated by route #1"#
+        );
+
+        let mixed = routes(
+            r#"
+[[route]]
+location = "/api/"
+root = "./html"
+proxy_pass = "http://localhost:3000"
+"#,
+        );
+        let err = check_routes(&mixed).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"route #0 "/api/" sets more than one of root, proxy_pass"#
+        );
+    }
 }
@@ -1,8 +1,15 @@
-use std::{borrow::Cow, collections::BTreeMap, env, sync::OnceLock};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    env,
+    sync::{LazyLock, OnceLock},
+    time::Instant,
+};
 
 use crate::{
-    config::{MIMEType, Settings},
+    config::{ByteSize, HumanDuration, MIMEType, Settings},
     error::{Error, Result},
+    utils::compress::CompressType,
 };
 
 // global settings
@@ -11,6 +18,9 @@ pub fn get_settings() -> Result<&'static Settings> {
     SETTINGS.get().ok_or(Error::Empty)
 }
 
+// process start time for uptime
+pub static START_TIME: LazyLock<Instant> = LazyLock::new(Instant::now);
+
 // pre defined
 pub const NAME: &str = env!("CARGO_PKG_NAME");
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -25,11 +35,55 @@ pub fn host_index() -> Vec<String> {
 }
 
 // default http connection timeout
-pub const TIMEOUT_EFAULT: u16 = 75;
-pub fn timeout_default() -> u16 {
+pub const TIMEOUT_EFAULT: HumanDuration = HumanDuration::from_secs(75);
+pub fn timeout_default() -> HumanDuration {
     TIMEOUT_EFAULT
 }
 
+// default time to read client request body before forward to upstream
+pub const CLIENT_BODY_TIMEOUT: HumanDuration = HumanDuration::from_secs(60);
+pub fn client_body_timeout_default() -> HumanDuration {
+    CLIENT_BODY_TIMEOUT
+}
+
+// default time to wait in-flight requests on shutdown
+pub const SHUTDOWN_TIMEOUT: HumanDuration = HumanDuration::from_secs(30);
+pub fn shutdown_timeout_default() -> HumanDuration {
+    SHUTDOWN_TIMEOUT
+}
+
+// merge consecutive slashes of request path by default
+pub const MERGE_SLASHES: bool = true;
+pub fn merge_slashes_default() -> bool {
+    MERGE_SLASHES
+}
+
+// content types never compressed by default, already compressed or streamed
+pub const COMPRESS_EXCLUDE_TYPES: [&str; 8] = [
+    "image/",
+    "video/",
+    "audio/",
+    "font/woff2",
+    "application/zip",
+    "application/gzip",
+    "application/octet-stream",
+    "text/event-stream",
+];
+pub fn compress_exclude_types_default() -> Vec<String> {
+    COMPRESS_EXCLUDE_TYPES.map(|t| t.to_string()).to_vec()
+}
+
+// compression preference of server when client accepts several
+pub const COMPRESS_PREFER: [CompressType; 4] = [
+    CompressType::Brotli,
+    CompressType::Zstd,
+    CompressType::Gzip,
+    CompressType::Deflate,
+];
+pub fn compress_prefer_default() -> Vec<CompressType> {
+    COMPRESS_PREFER.to_vec()
+}
+
 // default mime type for unknow file
 pub const MIME_DEFAULT: &str = "application/octet-stream";
 pub fn mime_default() -> Cow<'static, str> {
@@ -37,11 +91,53 @@ pub fn mime_default() -> Cow<'static, str> {
 }
 
 // default reverse proxy upstream timeout
-pub const UPSTREAM_TIMEOUT: u16 = 5;
-pub fn upstream_timeout_default() -> u16 {
+pub const UPSTREAM_TIMEOUT: HumanDuration = HumanDuration::from_secs(5);
+pub fn upstream_timeout_default() -> HumanDuration {
     UPSTREAM_TIMEOUT
 }
 
+// default reverse proxy upstream read timeout in milliseconds
+pub const UPSTREAM_READ_TIMEOUT: u64 = 60_000;
+pub fn upstream_read_timeout_default() -> u64 {
+    UPSTREAM_READ_TIMEOUT
+}
+
+// default max request body size kept in memory before spooled to disk
+pub const CLIENT_BODY_BUFFER_SIZE: ByteSize = ByteSize(16 * 1024);
+pub fn client_body_buffer_size_default() -> ByteSize {
+    CLIENT_BODY_BUFFER_SIZE
+}
+
+// default response status of denied request by filter
+pub const FILTER_STATUS: u16 = 403;
+pub fn filter_status_default() -> u16 {
+    FILTER_STATUS
+}
+
+// default response status of request rejected by limit_conn
+pub const LIMIT_CONN_STATUS: u16 = 503;
+pub fn limit_conn_status_default() -> u16 {
+    LIMIT_CONN_STATUS
+}
+
+// default response status of request with invalid Referer
+pub const INVALID_REFERER_STATUS: u16 = 403;
+pub fn invalid_referer_status_default() -> u16 {
+    INVALID_REFERER_STATUS
+}
+
+// add X-Candy-Version header to response
+pub const EXPOSE_VERSION: bool = true;
+pub fn expose_version_default() -> bool {
+    EXPOSE_VERSION
+}
+
+// strip route location from request path before forward to upstream
+pub const PROXY_STRIP_PREFIX: bool = true;
+pub fn proxy_strip_prefix_default() -> bool {
+    PROXY_STRIP_PREFIX
+}
+
 // default mime types
 pub fn types_default() -> MIMEType {
     BTreeMap::new()
@@ -2,7 +2,10 @@ use std::{borrow::Cow, io, num::TryFromIntError, sync::PoisonError, time::System
 
 use anyhow::anyhow;
 use http::uri::InvalidUri;
-use hyper::header::{InvalidHeaderValue, ToStrError};
+use hyper::{
+    header::{InvalidHeaderValue, ToStrError},
+    StatusCode,
+};
 
 #[allow(clippy::enum_variant_names)]
 #[derive(thiserror::Error, Debug)]
@@ -28,6 +31,28 @@ pub enum Error {
     // http
     #[error("route not found {0}")]
     NotFound(Cow<'static, str>),
+    #[error("bad request {0}")]
+    BadRequest(Cow<'static, str>),
+    #[error("request timeout {0}")]
+    Timeout(Cow<'static, str>),
+    #[error("gateway timeout {0}")]
+    GatewayTimeout(Cow<'static, str>),
+    #[error("upstream unavailable {0}")]
+    UpstreamUnavailable(Cow<'static, str>),
+    #[error("payload too large {0}")]
+    TooLarge(Cow<'static, str>),
+    #[error("forbidden {0}")]
+    Forbidden(Cow<'static, str>),
+    /// Methods allowed on the route, sent in `Allow` header
+    #[error("method not allowed, allow {0}")]
+    MethodNotAllowed(&'static str),
+    #[error("unauthorized {0}")]
+    Unauthorized(Cow<'static, str>),
+    #[error("host in maintenance")]
+    Maintenance,
+    /// Rejected before routing with status from config, like `limit_conn`
+    #[error("request rejected with {0}")]
+    Rejected(StatusCode),
     #[error("internal server error {0}")]
     InternalServerError(#[from] anyhow::Error),
     #[error("invalide header value {0}")]
@@ -40,6 +65,44 @@ pub enum Error {
 
 pub type Result<T, E = Error> = anyhow::Result<T, E>;
 
+impl Error {
+    /// HTTP status code response to client
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Timeout(_) => StatusCode::REQUEST_TIMEOUT,
+            Error::GatewayTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            Error::UpstreamUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::Maintenance => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Rejected(status) => *status,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Error code in structured error response
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::BadRequest(_) => "bad_request",
+            Error::NotFound(_) => "route_not_found",
+            Error::Timeout(_) => "request_timeout",
+            Error::GatewayTimeout(_) => "gateway_timeout",
+            Error::UpstreamUnavailable(_) => "upstream_unavailable",
+            Error::TooLarge(_) => "payload_too_large",
+            Error::Forbidden(_) => "forbidden",
+            Error::MethodNotAllowed(_) => "method_not_allowed",
+            Error::Unauthorized(_) => "unauthorized",
+            Error::Maintenance => "maintenance",
+            Error::Rejected(_) => "request_rejected",
+            _ => "internal_server_error",
+        }
+    }
+}
+
 impl<T> From<PoisonError<T>> for Error {
     fn from(err: PoisonError<T>) -> Self {
         Self::InternalServerError(anyhow!("global cache poisoned {err}"))
@@ -0,0 +1,281 @@
+use std::path::Path;
+
+use bytes::{Bytes, BytesMut};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Body;
+use tempfile::TempPath;
+use tokio::{
+    fs::File,
+    io::{AsyncWriteExt, BufReader},
+};
+use tracing::{debug, error};
+
+use crate::{
+    config::RequestBuffering,
+    error::{Error, Result},
+    http::{stream_file, CandyBody},
+};
+
+/// Request body spooled to a temp file
+/// The file will be removed when dropped, even if the client aborts
+#[derive(Debug)]
+pub struct TempBody {
+    /// Taken when dropped
+    path: Option<TempPath>,
+}
+
+impl TempBody {
+    /// Create an empty temp file under directory,
+    /// the name is random and the file is created exclusively with mode 0600
+    async fn create(dir: &str) -> Result<(Self, File)> {
+        let dir = dir.to_string();
+        let temp = tokio::task::spawn_blocking(move || {
+            tempfile::Builder::new()
+                .prefix("candy_body_")
+                .tempfile_in(dir)
+        })
+        .await
+        .map_err(|err| Error::InternalServerError(err.into()))??;
+        let (file, path) = temp.into_parts();
+        Ok((Self { path: Some(path) }, File::from_std(file)))
+    }
+
+    /// Path of temp file
+    pub fn path(&self) -> &Path {
+        self.path.as_deref().unwrap_or(Path::new(""))
+    }
+}
+
+impl Drop for TempBody {
+    fn drop(&mut self) {
+        let Some(path) = self.path.take() else {
+            return;
+        };
+        let remove = move || {
+            let file = path.to_path_buf();
+            if let Err(err) = path.close() {
+                error!("remove temp body {file:?} failed {err}");
+            }
+        };
+        // avoid blocking io on runtime threads
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => drop(handle.spawn_blocking(remove)),
+            Err(_) => remove(),
+        }
+    }
+}
+
+/// Client request body forward to upstream
+#[derive(Debug)]
+pub enum ProxyBody<B> {
+    /// Whole body in memory, can be replayed
+    Memory(Bytes),
+    /// Body spooled to temp file, can be replayed
+    Disk(TempBody),
+    /// Client body streamed to upstream directly, can only be sent once
+    Stream(Option<B>),
+}
+
+impl<B> ProxyBody<B>
+where
+    B: Body<Data = Bytes> + Send + Sync + Unpin + 'static,
+    B::Error: Into<Error>,
+{
+    /// Read client body by buffering mode
+    ///
+    /// ## Arguments
+    ///
+    /// `body`: client request body
+    /// `mode`: buffering mode from route
+    /// `buffer_size`: max body size kept in memory for disk mode
+    /// `max_size`: `client_max_body_size` of route, larger body is rejected with 413
+    /// `temp_path`: directory of temp files for disk mode
+    pub async fn new(
+        mut body: B,
+        mode: RequestBuffering,
+        buffer_size: u64,
+        max_size: Option<u64>,
+        temp_path: &str,
+    ) -> Result<Self> {
+        let too_large = |size: u64| {
+            max_size
+                .filter(|max| size > *max)
+                .map(|max| Error::TooLarge(format!("request body larger than {max} bytes").into()))
+        };
+        // exact for `Content-Length`, streamed body is checked by it only
+        if let Some(err) = too_large(body.size_hint().lower()) {
+            return Err(err);
+        }
+        if mode == RequestBuffering::Off {
+            return Ok(Self::Stream(Some(body)));
+        }
+
+        let mut buffer = BytesMut::new();
+        let mut spool = None;
+        let mut size = 0;
+        while let Some(frame) = body.frame().await {
+            let Ok(data) = frame.map_err(Into::into)?.into_data() else {
+                continue;
+            };
+            size += data.len() as u64;
+            if let Some(err) = too_large(size) {
+                return Err(err);
+            }
+            if spool.is_none()
+                && (mode == RequestBuffering::Memory
+                    || (buffer.len() + data.len()) as u64 <= buffer_size)
+            {
+                buffer.extend_from_slice(&data);
+                continue;
+            }
+            let (_, file) = match &mut spool {
+                Some(spool) => spool,
+                None => {
+                    let (temp, mut file) = TempBody::create(temp_path).await?;
+                    debug!("spool request body to {:?}", temp.path());
+                    file.write_all(&buffer).await?;
+                    spool.insert((temp, file))
+                }
+            };
+            file.write_all(&data).await?;
+        }
+        match spool {
+            Some((temp, mut file)) => {
+                file.flush().await?;
+                Ok(Self::Disk(temp))
+            }
+            None => Ok(Self::Memory(buffer.freeze())),
+        }
+    }
+
+    /// Body sent to upstream, used again when following redirects
+    ///
+    /// ## Return
+    ///
+    /// none when the streamed body was already sent
+    pub async fn replay(&mut self) -> Result<Option<CandyBody<Bytes>>> {
+        let body = match self {
+            Self::Memory(bytes) => Full::new(bytes.clone()).map_err(|e| match e {}).boxed(),
+            Self::Disk(temp) => {
+                let file = File::open(temp.path()).await?;
+                stream_file(BufReader::new(file)).await
+            }
+            Self::Stream(body) => match body.take() {
+                Some(body) => body.map_err(Into::into).boxed(),
+                None => return Ok(None),
+            },
+        };
+        Ok(Some(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use futures_util::stream;
+    use http_body_util::StreamBody;
+    use hyper::body::Frame;
+
+    use super::*;
+
+    const MB: usize = 1024 * 1024;
+
+    fn chunks(count: usize, fail: bool) -> impl Body<Data = Bytes, Error = Error> + Unpin {
+        let frames = (0..count).map(move |i| {
+            if fail && i == count - 1 {
+                Err(Error::Io(io::Error::other("client aborted")))
+            } else {
+                Ok(Frame::data(Bytes::from(vec![b'a'; MB])))
+            }
+        });
+        StreamBody::new(stream::iter(frames))
+    }
+
+    /// Wait temp files removed in background
+    async fn is_empty(dir: &Path) -> bool {
+        for _ in 0..100 {
+            if std::fs::read_dir(dir).unwrap().next().is_none() {
+                return true;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn disk_buffering_works() {
+        let dir = tempfile::tempdir().unwrap();
+        let temp_path = dir.path().to_str().unwrap();
+
+        let mut body = ProxyBody::new(
+            chunks(64, false),
+            RequestBuffering::Disk,
+            16 * 1024,
+            None,
+            temp_path,
+        )
+        .await
+        .unwrap();
+        assert!(matches!(body, ProxyBody::Disk(_)));
+        let ProxyBody::Disk(temp) = &body else {
+            unreachable!()
+        };
+        assert!(temp
+            .path()
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("candy_body_"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(temp.path()).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        for _ in 0..2 {
+            let replay = body.replay().await.unwrap().unwrap();
+            let bytes = replay.collect().await.unwrap().to_bytes();
+            assert_eq!(bytes.len(), 64 * MB);
+        }
+        drop(body);
+        assert!(is_empty(dir.path()).await);
+
+        // client aborts while spooling
+        let body = ProxyBody::new(
+            chunks(4, true),
+            RequestBuffering::Disk,
+            1024,
+            None,
+            temp_path,
+        )
+        .await;
+        assert!(body.is_err());
+        assert!(is_empty(dir.path()).await);
+
+        // small body stays in memory
+        let body = ProxyBody::new(
+            chunks(1, false),
+            RequestBuffering::Disk,
+            2 * MB as u64,
+            None,
+            temp_path,
+        )
+        .await
+        .unwrap();
+        assert!(matches!(body, ProxyBody::Memory(_)));
+        assert!(is_empty(dir.path()).await);
+
+        // larger than client_max_body_size, spooled file removed
+        for mode in [RequestBuffering::Memory, RequestBuffering::Disk] {
+            let body =
+                ProxyBody::new(chunks(4, false), mode, 1024, Some(2 * MB as u64), temp_path).await;
+            assert!(matches!(body, Err(Error::TooLarge(_))));
+        }
+        assert!(is_empty(dir.path()).await);
+        let body = Full::new(Bytes::from(vec![b'a'; 16])).map_err(|e| -> Error { match e {} });
+        let body = ProxyBody::new(body, RequestBuffering::Off, 1024, Some(8), temp_path).await;
+        assert!(matches!(body, Err(Error::TooLarge(_))));
+    }
+}
@@ -1,15 +1,33 @@
-use std::str::FromStr;
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    str::FromStr,
+    task::{Context as TaskContext, Poll},
+    time::Duration,
+};
 
 use anyhow::{anyhow, Context};
 use bytes::Bytes;
-use http::{request::Parts, HeaderValue, Request, Response, Uri};
-use http_body_util::Full;
-use hyper::body::Incoming;
+use http::{request::Parts, Response, Uri};
+use hyper::body::{Body, Frame, Incoming, SizeHint};
 use hyper_rustls::ConfigBuilderExt;
-use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
+use tokio::time::{sleep, Instant, Sleep};
 use tracing::debug;
 
-use crate::error::Error;
+use crate::{
+    config::ProxyHostHeader,
+    error::Error,
+    http::{
+        buffer::ProxyBody,
+        reverse_proxy::{set_host_header, strip_hop_by_hop_headers},
+        CandyBody,
+    },
+};
 
 const MAX_REDIRECTS: usize = 10;
 
@@ -20,11 +38,19 @@ const MAX_REDIRECTS: usize = 10;
 /// `url`: http url
 /// `parts`: http request parts
 /// `body`: http request body
+/// `connect_timeout`: timeout for establish connection to upstream
+/// `host_header`: how to send `Host` header to upstream
 ///
 /// ## Return
 ///
 /// `anyhow::Result<Response<Incoming>>`
-pub async fn get_inner(url: Uri, parts: Parts, body: Bytes) -> anyhow::Result<Response<Incoming>> {
+pub async fn get_inner(
+    url: Uri,
+    parts: Parts,
+    body: CandyBody<Bytes>,
+    connect_timeout: Duration,
+    host_header: &ProxyHostHeader,
+) -> anyhow::Result<Response<Incoming>> {
     // let _ = rustls::crypto::ring::default_provider().install_default();
     let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
 
@@ -35,27 +61,30 @@ pub async fn get_inner(url: Uri, parts: Parts, body: Bytes) -> anyhow::Result<Re
         .with_no_client_auth();
 
     // Prepare the HTTPS connector
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    http.set_connect_timeout(Some(connect_timeout));
     let https = hyper_rustls::HttpsConnectorBuilder::new()
         .with_tls_config(tls)
         .https_or_http()
         .enable_http1()
-        .build();
+        .wrap_connector(http);
 
     // Build the hyper client from the HTTPS connector.
-    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(https);
-    let host_url = url.clone();
-    let host = host_url.host().ok_or(Error::InternalServerError(anyhow!(
-        "proxy pass host incorrect"
-    )))?;
-    let mut req: Request<Full<Bytes>> = hyper::Request::builder()
+    let client: Client<_, CandyBody<Bytes>> = Client::builder(TokioExecutor::new()).build(https);
+    if url.host().is_none() {
+        return Err(anyhow!("proxy pass host incorrect"));
+    }
+    let upstream = url.clone();
+    let mut req = hyper::Request::builder()
         .method(parts.method.clone())
         .uri(url)
-        .body(Full::from(body))
+        .body(body)
         .with_context(|| "request builder")?;
-    // Add client request headers to request, and remove host header
+    // Add client request headers to request, then rewrite host header
     req.headers_mut().extend(parts.headers);
-    req.headers_mut()
-        .insert("host", HeaderValue::from_str(host)?);
+    strip_hop_by_hop_headers(req.headers_mut());
+    set_host_header(req.headers_mut(), host_header, &upstream, &parts.uri)?;
 
     let res = client.request(req).await?;
     Ok(res)
@@ -68,17 +97,34 @@ pub async fn get_inner(url: Uri, parts: Parts, body: Bytes) -> anyhow::Result<Re
 ///
 /// `url`: http url
 /// `parts`: http request parts
-/// `body`: http request body
+/// `body`: http request body, replayed when following redirects
+/// `connect_timeout`: timeout for establish connection to upstream
+/// `host_header`: how to send `Host` header to upstream
 ///
 /// ## Return
 ///
 /// `anyhow::Result<Response<Incoming>>`
-pub async fn get(url: Uri, parts: Parts, body: Bytes) -> anyhow::Result<Response<Incoming>> {
+pub async fn get<B>(
+    url: Uri,
+    parts: Parts,
+    body: &mut ProxyBody<B>,
+    connect_timeout: Duration,
+    host_header: &ProxyHostHeader,
+) -> anyhow::Result<Response<Incoming>>
+where
+    B: Body<Data = Bytes> + Send + Sync + Unpin + 'static,
+    B::Error: Into<Error>,
+{
     let mut redirects = 0;
 
-    let mut res = get_inner(url, parts.clone(), body.clone()).await?;
+    let body_inner = body.replay().await?.ok_or(Error::Empty)?;
+    let mut res = get_inner(url, parts.clone(), body_inner, connect_timeout, host_header).await?;
     while (res.status() == 301 || res.status() == 302) && redirects < MAX_REDIRECTS {
-        let (parts_inner, body_inner) = (parts.clone(), body.clone());
+        // streamed body can not be sent again
+        let Some(body_inner) = body.replay().await? else {
+            break;
+        };
+        let parts_inner = parts.clone();
         redirects += 1;
         let location = res
             .headers()
@@ -90,9 +136,72 @@ pub async fn get(url: Uri, parts: Parts, body: Bytes) -> anyhow::Result<Response
             .to_string();
         let url = Uri::from_str(&location).with_context(|| "failed to convert str to url")?;
         debug!("proxy redirect to {url}");
-        res = get_inner(url, parts_inner, body_inner).await?;
+        res = get_inner(url, parts_inner, body_inner, connect_timeout, host_header).await?;
     }
 
     debug!("get_inner response headers: {:?}", res.headers());
     Ok(res)
 }
+
+/// Check the error chain of upstream request is caused by timeout
+pub fn is_timeout(err: &anyhow::Error) -> bool {
+    err.chain().any(|err| {
+        err.downcast_ref::<io::Error>()
+            .is_some_and(|err| err.kind() == io::ErrorKind::TimedOut)
+    })
+}
+
+/// Upstream response body failed when no frame arrives within the read timeout,
+/// the timer restarts on every frame so long downloads are not cut off
+pub struct ReadTimeoutBody {
+    body: Incoming,
+    timeout: Duration,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl ReadTimeoutBody {
+    /// Wrap upstream response body
+    ///
+    /// ## Arguments
+    ///
+    /// `body`: upstream response body
+    /// `timeout`: max idle time between two frames, `proxy_read_timeout_ms` of route
+    pub fn new(body: Incoming, timeout: Duration) -> Self {
+        Self {
+            body,
+            timeout,
+            sleep: Box::pin(sleep(timeout)),
+        }
+    }
+}
+
+impl Body for ReadTimeoutBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = &mut *self;
+        if let Poll::Ready(frame) = Pin::new(&mut this.body).poll_frame(cx) {
+            let deadline = Instant::now() + this.timeout;
+            this.sleep.as_mut().reset(deadline);
+            return Poll::Ready(frame.map(|frame| frame.map_err(Error::HyperError)));
+        }
+        match this.sleep.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Some(Err(Error::GatewayTimeout(
+                "read upstream body timeout".into(),
+            )))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
@@ -0,0 +1,210 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use anyhow::anyhow;
+use http::Request;
+use regex::Regex;
+use tracing::warn;
+
+use crate::{
+    config::{FilterAction, SettingFilter},
+    error::Result,
+};
+
+/// Part of request checked by filter rule
+#[derive(Clone, Debug)]
+pub enum FilterTarget {
+    /// Request path
+    Uri,
+    /// Request query string
+    Query,
+    /// Request header value by name
+    Header(String),
+}
+
+/// How to match the target value
+#[derive(Clone, Debug)]
+pub enum FilterMatcher {
+    Regex(Regex),
+    Contains(String),
+}
+
+/// Request filter rule compiled from config
+/// Evaluated by order before routing
+#[derive(Clone, Debug)]
+pub struct RequestFilter {
+    /// Rule id in logs
+    pub id: String,
+    pub target: FilterTarget,
+    pub matcher: FilterMatcher,
+    pub action: FilterAction,
+    /// Response status for deny action, 400-599 or 444 means drop the connection
+    pub status: u16,
+    /// Matched requests count
+    hits: Arc<AtomicU64>,
+}
+
+impl RequestFilter {
+    /// Compile filter rule from config
+    ///
+    /// ## Arguments
+    ///
+    /// `index`: rule index in host, used as id when id is not set
+    /// `filter`: filter rule from config file
+    pub fn new(index: usize, filter: &SettingFilter) -> Result<Self> {
+        let id = filter.id.clone().unwrap_or_else(|| index.to_string());
+        let target = match filter.target.as_str() {
+            "uri" => FilterTarget::Uri,
+            "query" => FilterTarget::Query,
+            target => match target.strip_prefix("header:") {
+                Some(name) if !name.is_empty() => FilterTarget::Header(name.to_string()),
+                _ => return Err(anyhow!("request filter {id} invalid match {target}").into()),
+            },
+        };
+        let matcher = match (&filter.regex, &filter.contains) {
+            (Some(regex), None) => FilterMatcher::Regex(
+                Regex::new(regex).map_err(|err| anyhow!("request filter {id} {err}"))?,
+            ),
+            (None, Some(contains)) => FilterMatcher::Contains(contains.clone()),
+            _ => {
+                return Err(anyhow!("request filter {id} requires one of regex or contains").into())
+            }
+        };
+        // 444 is not a real status, it drops the connection
+        if !(400..=599).contains(&filter.status) && filter.status != 444 {
+            return Err(anyhow!(
+                "request filter {id} invalid status {}, expect 400-599 or 444",
+                filter.status
+            )
+            .into());
+        }
+        Ok(Self {
+            id,
+            target,
+            matcher,
+            action: filter.action,
+            status: filter.status,
+            hits: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// Check request matches this rule, count hits when matched
+    pub fn matches<T>(&self, req: &Request<T>) -> bool {
+        let value = match &self.target {
+            FilterTarget::Uri => Some(req.uri().path()),
+            FilterTarget::Query => Some(req.uri().query().unwrap_or("")),
+            FilterTarget::Header(name) => req
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok()),
+        };
+        let matched = value.is_some_and(|value| match &self.matcher {
+            FilterMatcher::Regex(regex) => regex.is_match(value),
+            FilterMatcher::Contains(contains) => value.contains(contains.as_str()),
+        });
+        if matched {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        matched
+    }
+
+    /// Matched requests count since server started
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+/// Evaluate filter rules by order
+///
+/// ## Arguments
+///
+/// `filters`: compiled filter rules of host
+/// `req`: client request
+///
+/// ## Return
+///
+/// the first matched deny rule, log rules only emit warning
+pub fn check_filters<'a, T>(
+    filters: &'a [RequestFilter],
+    req: &Request<T>,
+) -> Option<&'a RequestFilter> {
+    for filter in filters {
+        if !filter.matches(req) {
+            continue;
+        }
+        warn!(
+            "request filter {} matched {} {} hits {}",
+            filter.id,
+            req.method(),
+            req.uri(),
+            filter.hits()
+        );
+        if filter.action == FilterAction::Deny {
+            return Some(filter);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(target: &str, regex: Option<&str>, contains: Option<&str>) -> RequestFilter {
+        let filter = SettingFilter {
+            id: None,
+            target: target.into(),
+            regex: regex.map(Into::into),
+            contains: contains.map(Into::into),
+            action: FilterAction::Deny,
+            status: 444,
+        };
+        RequestFilter::new(0, &filter).unwrap()
+    }
+
+    #[test]
+    fn request_filter_works() {
+        let req = Request::builder()
+            .uri("/index.php?id=1%20union%20select")
+            .header("User-Agent", "sqlmap/1.0")
+            .body(())
+            .unwrap();
+        let uri = filter("uri", Some(r"\.(php|asp)$"), None);
+        assert!(uri.matches(&req));
+        assert_eq!(uri.hits(), 1);
+        assert!(filter("query", Some(r"union%20select"), None).matches(&req));
+        assert!(filter("header:User-Agent", None, Some("sqlmap")).matches(&req));
+        assert!(!filter("header:Referer", None, Some("sqlmap")).matches(&req));
+
+        let mut log = filter("uri", None, Some("/index"));
+        log.action = FilterAction::Log;
+        let filters = [log, filter("header:User-Agent", None, Some("curl")), uri];
+        let denied = check_filters(&filters, &req).unwrap();
+        assert_eq!(denied.status, 444);
+        assert_eq!(filters[0].hits(), 1);
+        assert_eq!(filters[1].hits(), 0);
+    }
+
+    #[test]
+    fn request_filter_invalid() {
+        let filter = SettingFilter {
+            id: Some("bad".into()),
+            target: "body".into(),
+            regex: None,
+            contains: Some("x".into()),
+            action: FilterAction::Log,
+            status: 403,
+        };
+        assert!(RequestFilter::new(0, &filter).is_err());
+        for status in [0, 99, 101, 200, 302, 600, 1000] {
+            let filter = SettingFilter {
+                target: "uri".into(),
+                status,
+                ..filter.clone()
+            };
+            assert!(RequestFilter::new(0, &filter).is_err());
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::fs;
+
+use anyhow::{anyhow, Context};
+use http::{HeaderMap, HeaderName, HeaderValue};
+use regex::Regex;
+
+use crate::error::Result;
+
+/// Path pattern and headers of one rule in `_headers` file
+#[derive(Clone, Debug)]
+struct HeaderRule {
+    pattern: Regex,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+/// Response headers by request path, parsed from Netlify style `_headers` file
+///
+/// ```text
+/// # comment
+/// /assets/*
+///   Cache-Control: public, max-age=31536000
+/// /blog/:slug
+///   X-Frame-Options: DENY
+/// ```
+#[derive(Clone, Debug)]
+pub struct HeadersFile {
+    rules: Vec<HeaderRule>,
+}
+
+/// Compile path pattern to regex,
+/// `*` matches anything and a whole `:name` segment matches one path segment
+fn compile_pattern(pattern: &str) -> Result<Regex> {
+    let segments = pattern
+        .split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) if !name.is_empty() => "[^/]+".to_string(),
+            _ => segment
+                .split('*')
+                .map(regex::escape)
+                .collect::<Vec<_>>()
+                .join(".*"),
+        })
+        .collect::<Vec<_>>();
+    let regex = format!("^{}$", segments.join("/"));
+    Ok(Regex::new(&regex).map_err(|err| anyhow!("invalid path {pattern} {err}"))?)
+}
+
+impl HeadersFile {
+    /// Read and parse `_headers` file
+    ///
+    /// ## Arguments
+    ///
+    /// `path`: `headers_file` of route
+    pub fn load(path: &str) -> Result<Self> {
+        let content =
+            fs::read_to_string(path).with_context(|| format!("read headers file {path} failed"))?;
+        Ok(Self::parse(&content).with_context(|| format!("parse headers file {path} failed"))?)
+    }
+
+    /// Parse `_headers` file content, path lines start at column 0
+    /// and header lines under it are indented
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut rules: Vec<HeaderRule> = vec![];
+        for (index, line) in content.lines().enumerate() {
+            let text = line.trim();
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+            if !line.starts_with(char::is_whitespace) {
+                rules.push(HeaderRule {
+                    pattern: compile_pattern(text)?,
+                    headers: vec![],
+                });
+                continue;
+            }
+            let line_number = index + 1;
+            let rule = rules
+                .last_mut()
+                .ok_or_else(|| anyhow!("line {line_number} header without path"))?;
+            let (name, value) = text
+                .split_once(':')
+                .ok_or_else(|| anyhow!("line {line_number} invalid header {text}"))?;
+            let name = HeaderName::from_bytes(name.trim().as_bytes())
+                .map_err(|err| anyhow!("line {line_number} {err}"))?;
+            rule.headers.push((name, value.trim().parse()?));
+        }
+        Ok(Self { rules })
+    }
+
+    /// Insert headers of all rules matching request path,
+    /// later rules override earlier ones with the same header name
+    ///
+    /// ## Arguments
+    ///
+    /// `path`: client request path
+    /// `headers`: response headers
+    pub fn apply(&self, path: &str, headers: &mut HeaderMap) {
+        for rule in self.rules.iter().filter(|rule| rule.pattern.is_match(path)) {
+            for (name, value) in &rule.headers {
+                headers.insert(name, value.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADERS: &str = r#"
+# Netlify style headers
+/*
+  X-Frame-Options: DENY
+  Cache-Control: no-cache
+
+/assets/*
+  Cache-Control: public, max-age=31536000
+
+/index.html
+  X-Frame-Options: SAMEORIGIN
+
+/blog/:slug/
+  X-Robots-Tag: noindex
+"#;
+
+    fn headers(file: &HeadersFile, path: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        // host level headers are added before
+        headers.insert("Cache-Control", "max-age=60".parse().unwrap());
+        file.apply(path, &mut headers);
+        headers
+    }
+
+    #[test]
+    fn headers_file_works() {
+        let file = HeadersFile::parse(HEADERS).unwrap();
+
+        let res = headers(&file, "/index.html");
+        assert_eq!(res["X-Frame-Options"], "SAMEORIGIN");
+        assert_eq!(res["Cache-Control"], "no-cache");
+
+        let res = headers(&file, "/assets/js/app.js");
+        assert_eq!(res["X-Frame-Options"], "DENY");
+        assert_eq!(res["Cache-Control"], "public, max-age=31536000");
+
+        let res = headers(&file, "/blog/hello/");
+        assert_eq!(res["X-Robots-Tag"], "noindex");
+        assert!(!headers(&file, "/blog/hello/world/").contains_key("X-Robots-Tag"));
+        assert!(!headers(&file, "/index.htm").contains_key("X-Robots-Tag"));
+
+        let file = HeadersFile::parse("/exact.html\n  X-Exact: 1\n").unwrap();
+        assert_eq!(headers(&file, "/exact.html")["X-Exact"], "1");
+        assert!(!headers(&file, "/exact.htmlx").contains_key("X-Exact"));
+        // dots are literal
+        assert!(!headers(&file, "/exactXhtml").contains_key("X-Exact"));
+
+        // colon inside a segment is literal
+        let file = HeadersFile::parse("/time/12:30\n  X-Time: 1\n").unwrap();
+        assert_eq!(headers(&file, "/time/12:30")["X-Time"], "1");
+        assert!(!headers(&file, "/time/12abc").contains_key("X-Time"));
+
+        assert!(HeadersFile::parse("  X-Orphan: 1\n").is_err());
+        assert!(HeadersFile::parse("/\n  missing colon\n").is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use std::sync::atomic::Ordering;
+
+use http::{Response, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+
+use crate::{
+    config::SettingHost,
+    consts::{START_TIME, VERSION},
+    http::{mime::APPLICATION_JSON, CandyBody},
+};
+
+/// Built-in health check response of host,
+/// 200 while serving and 503 in maintenance or once graceful shutdown has begun
+///
+/// ## Arguments
+///
+/// `host`: host of the health endpoint
+pub fn health_response(host: &SettingHost) -> Response<CandyBody<Bytes>> {
+    let (status, state) = if host.draining.load(Ordering::Relaxed) {
+        (StatusCode::SERVICE_UNAVAILABLE, "draining")
+    } else if host.maintenance.load(Ordering::Relaxed) {
+        (StatusCode::SERVICE_UNAVAILABLE, "maintenance")
+    } else {
+        (StatusCode::OK, "ok")
+    };
+    let body = format!(
+        r#"{{"status":"{state}","version":"{VERSION}","uptime":{}}}"#,
+        START_TIME.elapsed().as_secs()
+    );
+    Response::builder()
+        .status(status)
+        .header("Content-Type", APPLICATION_JSON)
+        .header("Cache-Control", "no-store")
+        .body(Full::new(body.into()).map_err(|e| match e {}).boxed())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn health_response_works() {
+        let settings: crate::config::Settings = toml::from_str(
+            r#"
+[[host]]
+ip = "127.0.0.1"
+port = 4000
+health_endpoint = "/healthz"
+route = []
+"#,
+        )
+        .unwrap();
+        let host = &settings.host[0];
+        let res = health_response(host);
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["Content-Type"], APPLICATION_JSON);
+        let body = futures_util::FutureExt::now_or_never(res.into_body().collect())
+            .unwrap()
+            .unwrap()
+            .to_bytes();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.starts_with(&format!(
+            r#"{{"status":"ok","version":"{VERSION}","uptime":"#
+        )));
+
+        host.maintenance.store(true, Ordering::Relaxed);
+        let res = health_response(host);
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        host.maintenance.store(false, Ordering::Relaxed);
+
+        host.draining.store(true, Ordering::Relaxed);
+        assert_eq!(
+            health_response(host).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+}
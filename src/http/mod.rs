@@ -1,5 +1,12 @@
+pub mod buffer;
 pub mod client;
+pub mod filter;
+pub mod headers_file;
+pub mod health;
 pub mod mime;
 pub mod response;
+pub mod reverse_proxy;
+pub mod split;
+pub mod vfs;
 
 pub use response::*;
@@ -1,36 +1,55 @@
 use std::{
+    io,
+    net::SocketAddr,
     path::{Path, PathBuf},
     str::FromStr,
-    time::{Duration, UNIX_EPOCH},
+    time::Duration,
 };
 
+#[cfg(feature = "embed")]
+use crate::http::vfs::{EmbedFs, EMBED_DIR};
 use crate::{
-    config::{SettingHost, SettingRoute},
+    config::{ErrorFormat, ReturnRoute, ServerTokens, SettingCookie, SettingHost, SettingRoute},
     consts::{NAME, VERSION},
     error::{Error, Result},
     get_settings,
-    http::client,
+    http::{
+        buffer::ProxyBody,
+        client,
+        mime::{APPLICATION_JSON, TEXT_HTML, TEXT_PLAIN},
+        reverse_proxy::strip_hop_by_hop_headers,
+        vfs::{AsyncVfs, TokioFs, VfsFile, EMBED_PREFIX},
+    },
     utils::{
-        compress::{stream_compress, CompressType},
-        find_route, parse_assets_path,
+        compress::{
+            encoded_etag, is_compressible, stream_compress, strip_encoded_etags, CompressType,
+        },
+        find_route,
+        geoip::country_code,
+        host::request_host,
+        limit::limit_rate,
+        negotiate::{negotiate_index, negotiate_variants},
+        parse_assets_path, parse_file_path, parse_proxy_path,
+        referer::valid_referer,
+        variables::expand_variables,
     },
 };
 
 use anyhow::{anyhow, Context};
 use futures_util::TryStreamExt;
-use http::{response::Builder, Method};
+use http::{response::Builder, HeaderMap, HeaderValue, Method};
 use http_body_util::{combinators::BoxBody, BodyExt, Full, StreamBody};
 use hyper::{
     body::{Bytes, Frame, Incoming},
     Request, Response, StatusCode,
 };
 
+use sha2::{Digest, Sha256};
 use tokio::{
     fs::File,
-    io::{AsyncBufRead, BufReader},
-    select,
+    io::{AsyncBufRead, AsyncReadExt, AsyncSeekExt, SeekFrom},
 };
-use tokio_util::io::ReaderStream;
+use tokio_util::io::{ReaderStream, StreamReader};
 use tracing::{debug, error, instrument};
 
 /// Candy handler
@@ -45,6 +64,8 @@ pub struct CandyHandler<'req> {
     pub res: Builder,
     /// Config host field
     host: &'static SettingHost,
+    /// Client socket address
+    peer_addr: SocketAddr,
     /// Router
     router: Option<&'req SettingRoute>,
     /// Current request's assets path
@@ -55,11 +76,12 @@ pub type CandyBody<T, E = Error> = BoxBody<T, E>;
 type CandyResponse = Result<Response<CandyBody<Bytes>>>;
 impl CandyHandler<'_> {
     /// Create a new handler with hyper incoming request
-    pub fn new(req: Request<Incoming>, host: &'static SettingHost) -> Self {
+    pub fn new(req: Request<Incoming>, host: &'static SettingHost, peer_addr: SocketAddr) -> Self {
         Self {
             req,
             res: Response::builder(),
             host,
+            peer_addr,
             router: None,
             assets_path: None,
         }
@@ -72,32 +94,50 @@ impl CandyHandler<'_> {
             .res
             .headers_mut()
             .ok_or(Error::InternalServerError(anyhow!("build response failed")))?;
-        let server = format!("{}/{}", NAME, VERSION);
-        headers.insert("Server", server.parse()?);
-        // config headers overrite
-        if let Some(c_headers) = &self.host.headers {
-            for (k, v) in c_headers {
-                headers.insert(k.as_str(), v.parse()?);
-            }
-        }
-        Ok(())
+        set_host_headers(headers, self.host)
     }
 
     /// Handle static file or reverse proxy
+    ///
+    /// Routes are matched by path only, every request method reaches the route.
+    /// Literal responses answer any method, reverse proxy forwards the method to upstream
+    /// as is, and static files are served for GET and HEAD only, other methods get 405.
     pub async fn handle(mut self) -> CandyResponse {
         let uri = self.req.uri().clone();
         let req_path = uri.path();
+        // reject ambiguous or malformed host
+        request_host(&self.req)?;
         // find route path
-        let (router, assets_path) = find_route(req_path, &self.host.route_map)?;
+        let fallback = self.host.fallback.as_ref();
+        let (router, assets_path) = find_route(req_path, &self.host.route_map, fallback)?;
         self.router = Some(router);
         self.assets_path = Some(assets_path);
+        // country based access
+        if !router.deny_countries.is_empty() {
+            let country = country_code(self.peer_addr.ip());
+            if router.deny_countries.iter().any(|deny| deny == country) {
+                return Err(Error::Forbidden(
+                    format!("country {country} denied {req_path}").into(),
+                ));
+            }
+        }
 
-        // reverse proxy
-        if router.proxy_pass.is_some() {
+        let res = if let Some(ret) = &router.return_route {
+            // literal response
+            handle_return(&self.req, self.res, ret, &self.peer_addr)
+        } else if router.proxy_pass.is_some() || router.splitter.is_some() {
+            // reverse proxy
             self.proxy().await
         } else {
             // static file
             self.file().await
+        };
+        match router.limit_rate {
+            Some(rate) => {
+                let after = router.limit_rate_after.0;
+                res.map(|res| res.map(|body| limit_rate(body, rate.0, after)))
+            }
+            None => res,
         }
     }
 
@@ -112,15 +152,24 @@ impl CandyHandler<'_> {
                 .ok_or(Error::NotFound("handler assets_path is empty".into()))?,
         );
         let (req, mut res) = (self.req, self.res);
-        let (parts, body) = req.into_parts();
+        let (mut parts, body) = req.into_parts();
+        // client validates with the ETag of compressed response
+        if router.proxy_compress {
+            for name in ["If-None-Match", "If-Match"] {
+                let Some(value) = parts.headers.get(name).and_then(|v| v.to_str().ok()) else {
+                    continue;
+                };
+                let value = strip_encoded_etags(value);
+                parts.headers.insert(name, value.parse()?);
+            }
+        }
 
-        let assets_path = if !assets_path.is_empty() {
-            format!("/{assets_path}")
-        } else {
-            "".to_string()
-        };
+        let assets_path = parse_proxy_path(router, parts.uri.path(), assets_path);
         // check on outside
-        let proxy = router.proxy_pass.as_ref().ok_or(Error::Empty)?;
+        let proxy = match &router.splitter {
+            Some(splitter) => splitter.choose(&parts.headers),
+            None => router.proxy_pass.as_ref().ok_or(Error::Empty)?,
+        };
         let proxy = proxy.trim_end_matches('/');
         let path_query = parts.uri.query().unwrap_or("");
         let path_query = if !path_query.is_empty() {
@@ -138,71 +187,232 @@ impl CandyHandler<'_> {
         )))?;
         let uri = uri.clone();
         debug!("proxy pass to: {uri}");
-        let timeout = router.proxy_timeout;
-        let body = body.collect().await?.to_bytes();
-        let body = select! {
-            body = client::get(uri, parts, body) => {
-                body.with_context(|| "proxy body error")?
+        let connect_timeout = router
+            .proxy_connect_timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(router.proxy_timeout.0);
+        let read_timeout = Duration::from_millis(router.proxy_read_timeout_ms);
+        let method = parts.method.clone();
+        let accept_encoding = parts
+            .headers
+            .get("Accept-Encoding")
+            .and_then(|accept| accept.to_str().ok())
+            .map(str::to_string);
+        let temp_path = router
+            .client_body_temp_path
+            .clone()
+            .unwrap_or_else(|| std::env::temp_dir().to_string_lossy().to_string());
+        let mut body = tokio::time::timeout(
+            self.host.client_body_timeout.0,
+            ProxyBody::new(
+                body,
+                router.proxy_request_buffering,
+                router.client_body_buffer_size.0,
+                router.client_max_body_size.map(|size| size.0),
+                &temp_path,
+            ),
+        )
+        .await
+        .map_err(|_| Error::Timeout("read request body timeout".into()))??;
+        // proxy_timeout bounds the whole upstream request until response headers,
+        // proxy_read_timeout_ms bounds waiting for headers and each body frame
+        let upstream = tokio::time::timeout(
+            read_timeout,
+            client::get(
+                uri,
+                parts,
+                &mut body,
+                connect_timeout,
+                &router.proxy_host_header,
+            ),
+        );
+        let body = tokio::time::timeout(router.proxy_timeout.0, upstream)
+            .await
+            .map_err(|_| Error::GatewayTimeout(format!("upstream {host:?} timeout").into()))?
+            .map_err(|_| Error::GatewayTimeout(format!("read upstream {host:?} timeout").into()))?
+            .map_err(|err| {
+                if client::is_timeout(&err) {
+                    Error::GatewayTimeout(format!("connect upstream {host:?} timeout").into())
+                } else {
+                    Error::UpstreamUnavailable(format!("proxy {host:?} failed: {err}").into())
+                }
+            })?;
+        let body = body.map(|body| client::ReadTimeoutBody::new(body, read_timeout));
+        res = res.status(body.status());
+        let headers = res
+            .headers_mut()
+            .ok_or(Error::MissingHeader("missing response headers"))
+            .with_context(|| "build response failed")?;
+        let mut upstream_headers = body.headers().clone();
+        strip_hop_by_hop_headers(&mut upstream_headers);
+        headers.extend(upstream_headers);
+        set_server_headers(headers, self.host)?;
+        // compress upstream response on the fly, avoid double compress
+        let mut encoding = None;
+        if router.proxy_compress && !body.headers().contains_key("Content-Encoding") {
+            let compression = &get_settings()?.compression;
+            let content_type = body
+                .headers()
+                .get("Content-Type")
+                .and_then(|content_type| content_type.to_str().ok())
+                .unwrap_or_default();
+            if is_compressible(content_type, &compression.exclude_types) {
+                headers.append("Vary", "Accept-Encoding".parse()?);
+                encoding = accept_encoding
+                    .as_deref()
+                    .and_then(|accept| CompressType::from_accept(accept, &compression.prefer));
             }
-            _ = tokio::time::sleep(Duration::from_secs(timeout.into())) => {
-                return Err(anyhow!("connect upstream {host:?} timeout").into());
+        }
+        // same ETag as GET, but HEAD, 1xx, 204 and 304 have no body to compress
+        let status = body.status();
+        let bodyless = method == Method::HEAD
+            || status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED;
+        if let Some(encoding) = encoding {
+            if let Some(etag) = headers.get("ETag").and_then(|etag| etag.to_str().ok()) {
+                let etag = encoded_etag(etag, encoding);
+                headers.insert("ETag", etag.parse()?);
             }
-        };
-        res.headers_mut()
-            .ok_or(Error::MissingHeader("missing response headers"))
-            .with_context(|| "build response failed")?
-            .extend(body.headers().clone());
-        let res_body = res.body(body.map_err(Error::HyperError).boxed())?;
+        }
+        if let Some(encoding) = encoding.filter(|_| !bodyless) {
+            // compressed body is chunked
+            headers.remove("Content-Length");
+            headers.insert("Content-Encoding", encoding.name().parse()?);
+            let stream = body
+                .into_body()
+                .into_data_stream()
+                .map_err(io::Error::other);
+            let res_body = stream_compress(encoding, StreamReader::new(stream));
+            return Ok(res.body(res_body)?);
+        }
+        let res_body = res.body(body.into_body().boxed())?;
         Ok(res_body)
     }
 
     /// Handle static files,
-    /// try find static file from local path
+    /// try find static file from local path or files embedded in binary
     ///
     /// Only use with the `proxy_pass` field not in config
     pub async fn file(self) -> CandyResponse {
+        match self.router.and_then(|router| router.root.as_deref()) {
+            Some(root) if root.starts_with(EMBED_PREFIX) => {
+                #[cfg(feature = "embed")]
+                return self.serve(&EmbedFs(&EMBED_DIR)).await;
+                #[cfg(not(feature = "embed"))]
+                return Err(Error::InternalServerError(anyhow!(
+                    "root {root} requires candy built with the `embed` feature"
+                )));
+            }
+            _ => self.serve(&TokioFs).await,
+        }
+    }
+
+    /// Serve static file from filesystem
+    async fn serve(self, vfs: &impl AsyncVfs) -> CandyResponse {
         let (router, assets_path) = (
             self.router
                 .ok_or(Error::NotFound("handler router is empty".into()))?,
             self.assets_path
                 .ok_or(Error::NotFound("handler assets_path is empty".into()))?,
         );
-        let (req, res) = (self.req, self.res);
+        let (req, mut res) = (self.req, self.res);
 
-        let req_method = req.method();
+        // static files only answer GET and HEAD
+        match *req.method() {
+            Method::GET | Method::HEAD => {}
+            Method::OPTIONS => {
+                let res = res
+                    .status(StatusCode::NO_CONTENT)
+                    .header("Allow", STATIC_ALLOW_METHODS);
+                return Ok(res.body(CandyBody::default())?);
+            }
+            _ => return Err(Error::MethodNotAllowed(STATIC_ALLOW_METHODS)),
+        }
+
+        let types = &get_settings()?.types;
 
         // find resource local file path
+        // try the file itself first, then the index files of directory by `Accept` and order
+        let mut vary_accept = false;
         let mut path = None;
-        for index in router.index.iter() {
-            if let Some(root) = &router.root {
-                let p = parse_assets_path(assets_path, root, index);
-                if Path::new(&p).exists() {
-                    path = Some(p);
-                    break;
+        if let Some(root) = &router.root {
+            let file_path = parse_file_path(assets_path, root);
+            if !assets_path.ends_with('/') && vfs.is_file(&file_path).await {
+                path = Some(file_path);
+            } else {
+                let mut indexes = vec![];
+                for index in &router.index {
+                    let index = parse_assets_path(assets_path, root, index);
+                    if vfs.is_file(&index).await {
+                        indexes.push(index);
+                    }
                 }
+                vary_accept = indexes.len() > 1;
+                path = negotiate_index(indexes, req.headers(), types);
             }
         }
+        if vary_accept && !router.content_negotiation {
+            res = res.header("Vary", "Accept");
+        }
         let path = match path {
             Some(p) => p,
             None => {
-                return handle_not_found(req, res, router, "").await;
+                return handle_not_found(req, res, router, "", vfs).await;
             }
         };
-
-        // http method handle
-        let res = match *req_method {
-            Method::GET => handle_get(req, res, &path).await?,
-            Method::POST => handle_get(req, res, &path).await?,
-            // Return the 404 Not Found for other routes.
-            _ => {
-                if let Some(err_page) = &router.error_page {
-                    let res = res.status(err_page.status);
-                    handle_get(req, res, &err_page.page).await?
-                } else {
-                    not_found()
+        // hotlink protection
+        if let Some(valid_referers) = &router.valid_referers {
+            let protected = router.protect_extensions.is_empty()
+                || Path::new(&path)
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| {
+                        router
+                            .protect_extensions
+                            .iter()
+                            .any(|protect| protect.eq_ignore_ascii_case(ext))
+                    });
+            let referer = req
+                .headers()
+                .get("Referer")
+                .map(|referer| referer.to_str().unwrap_or_default());
+            let host = request_host(&req)?.map(|(name, _)| name);
+            if protected && !valid_referer(referer, valid_referers, host) {
+                debug!("invalid referer {referer:?} of {path}");
+                let Some(action) = &router.invalid_referer_action else {
+                    return Err(Error::Forbidden(format!("invalid referer {path}").into()));
+                };
+                let res = match &action.redirect {
+                    Some(redirect) => res
+                        .status(StatusCode::FOUND)
+                        .header("Location", redirect.as_str()),
+                    None => res.status(action.status),
+                };
+                return Ok(res.body(Full::new(Bytes::new()).map_err(|e| match e {}).boxed())?);
+            }
+        }
+        // try localised or typed variant of file
+        let mut path = path;
+        if router.content_negotiation {
+            let headers = res
+                .headers_mut()
+                .ok_or(Error::InternalServerError(anyhow!("build response failed")))?;
+            headers.append("Vary", "Accept".parse()?);
+            headers.append("Vary", "Accept-Language".parse()?);
+            for variant in negotiate_variants(&path, req.headers(), types) {
+                if vfs.is_file(&variant).await {
+                    path = variant;
+                    break;
                 }
             }
-        };
+        }
+        let req_path = req.uri().path().to_string();
+        let mut res = handle_get(req, res, &path, router, vfs).await?;
+        // per path headers from headers file, override host and file headers
+        if let Some(header_rules) = &router.header_rules {
+            header_rules.apply(&req_path, res.headers_mut());
+        }
         Ok(res)
     }
 }
@@ -226,6 +436,39 @@ pub async fn open_file(path: &str) -> Result<File> {
     Ok(file)
 }
 
+/// Methods allowed on static file routes, the `Allow` header of 405 and OPTIONS
+pub const STATIC_ALLOW_METHODS: &str = "GET, HEAD, OPTIONS";
+
+/// Max file size for strong ETag, larger files fall back to weak ETag
+pub const ETAG_STRONG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+/// Bytes read from the head and tail of file for strong ETag
+const ETAG_STRONG_CHUNK: u64 = 64 * 1024;
+
+/// Calculate strong ETag by file content,
+/// hash the first and last 64 KB of file with its size.
+/// The file cursor will be reset to the start.
+///
+/// ## Arguments
+///
+/// `file`: opened local file
+/// `size`: file size
+pub async fn strong_etag(file: &mut File, size: u64) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; ETAG_STRONG_CHUNK.min(size).try_into()?];
+    file.read_exact(&mut buffer).await?;
+    hasher.update(&buffer);
+    if size > ETAG_STRONG_CHUNK {
+        let tail_start = ETAG_STRONG_CHUNK.max(size - ETAG_STRONG_CHUNK);
+        file.seek(SeekFrom::Start(tail_start)).await?;
+        let mut buffer = vec![0u8; (size - tail_start).try_into()?];
+        file.read_exact(&mut buffer).await?;
+        hasher.update(&buffer);
+    }
+    hasher.update(size.to_be_bytes());
+    file.seek(SeekFrom::Start(0)).await?;
+    Ok(format!("\"{:x}\"", hasher.finalize()))
+}
+
 /// Open then use `ReaderStream` to stream to client.
 /// Stream a file more suitable for large file, but its slower than read file to memory.
 pub async fn stream_file<R>(file: R) -> CandyBody<Bytes>
@@ -253,50 +496,188 @@ where
 //     Ok(body)
 // }
 
-// HTTP status code 404
-static NOT_FOUND: &[u8] = b"Not Found";
-pub fn not_found() -> Response<CandyBody<Bytes>> {
-    Response::builder()
-        .status(StatusCode::NOT_FOUND)
-        .body(Full::new(NOT_FOUND.into()).map_err(|e| match e {}).boxed())
-        .unwrap()
+/// Add `Server`, config headers and cookies of host to response headers,
+/// used by handled responses and responses rejected before routing
+///
+/// ## Arguments
+///
+/// `headers`: response headers
+/// `host`: host from config file
+pub fn set_host_headers(headers: &mut HeaderMap, host: &'static SettingHost) -> Result<()> {
+    set_server_headers(headers, host)?;
+    // config headers overrite
+    if let Some(c_headers) = &host.headers {
+        for (k, v) in c_headers {
+            headers.insert(k.as_str(), v.parse()?);
+        }
+    }
+    // config cookies, multiple Set-Cookie headers can coexist
+    if let Some(cookies) = &host.cookies {
+        for cookie in cookies {
+            headers.append("Set-Cookie", set_cookie_value(cookie).parse()?);
+        }
+    }
+    Ok(())
 }
 
-static INTERNAL_SERVER_ERROR: &[u8] = b"Internal Server Error";
-pub fn internal_server_error() -> Response<CandyBody<Bytes>> {
-    Response::builder()
-        .status(StatusCode::INTERNAL_SERVER_ERROR)
-        .body(
-            Full::new(INTERNAL_SERVER_ERROR.into())
-                .map_err(|e| match e {})
-                .boxed(),
-        )
+/// Set `Server` and `X-Candy-Version` headers by `server_tokens` of host,
+/// applied again after upstream headers are merged into proxy response,
+/// `server_tokens` is resolved from legacy options when config is loaded
+///
+/// ## Arguments
+///
+/// `headers`: response headers
+/// `host`: host from config file
+pub fn set_server_headers(headers: &mut HeaderMap, host: &SettingHost) -> Result<()> {
+    let server = match host.server_tokens.as_ref().unwrap_or(&ServerTokens::On) {
+        ServerTokens::Off => {
+            headers.remove("Server");
+            headers.remove("X-Candy-Version");
+            return Ok(());
+        }
+        // keep the server header sent by upstream
+        ServerTokens::On if headers.contains_key("Server") => None,
+        ServerTokens::On => Some(HeaderValue::from_static(NAME)),
+        ServerTokens::Custom(server) => Some(server.clone()),
+    };
+    if let Some(server) = server {
+        headers.insert("Server", server);
+    }
+    if host.expose_version {
+        headers.insert("X-Candy-Version", VERSION.parse()?);
+    }
+    Ok(())
+}
+
+/// Build error response by error kind
+///
+/// ## Arguments
+///
+/// `err`: error from handler
+/// `format`: response body format
+pub fn error_response(err: &Error, format: ErrorFormat) -> Response<CandyBody<Bytes>> {
+    let status = err.status();
+    let reason = status.canonical_reason().unwrap_or("Unknown Error");
+    let (content_type, body) = match format {
+        ErrorFormat::Json => (
+            APPLICATION_JSON,
+            format!(r#"{{"error":"{}","message":"{}"}}"#, err.code(), reason),
+        ),
+        ErrorFormat::Text => (TEXT_PLAIN, reason.to_string()),
+        ErrorFormat::Html => (
+            TEXT_HTML,
+            format!(
+                "<html><head><title>{status}</title></head><body><h1>{status}</h1></body></html>"
+            ),
+        ),
+    };
+    let res = Response::builder()
+        .status(status)
+        .header("Content-Type", content_type);
+    let res = match err {
+        Error::MethodNotAllowed(allow) => res.header("Allow", *allow),
+        Error::Unauthorized(_) => res.header("WWW-Authenticate", "Bearer"),
+        _ => res,
+    };
+    res.body(Full::new(body.into()).map_err(|e| match e {}).boxed())
         .unwrap()
 }
 
+/// Decide error response format from host config or client `Accept` header
+///
+/// ## Arguments
+///
+/// `host`: host from config file
+/// `accept`: client `Accept` header
+pub fn error_format(host: &SettingHost, accept: Option<&HeaderValue>) -> ErrorFormat {
+    if let Some(format) = host.error_format {
+        return format;
+    }
+    match accept.and_then(|accept| accept.to_str().ok()) {
+        Some(accept) if accept.contains(APPLICATION_JSON) => ErrorFormat::Json,
+        _ => ErrorFormat::Text,
+    }
+}
+
+/// Format cookie from config to `Set-Cookie` header value
+/// https://datatracker.ietf.org/doc/html/rfc6265#section-4.1
+pub fn set_cookie_value(cookie: &SettingCookie) -> String {
+    let mut value = format!("{}={}", cookie.name, cookie.value);
+    if let Some(path) = &cookie.path {
+        value.push_str(&format!("; Path={path}"));
+    }
+    if let Some(domain) = &cookie.domain {
+        value.push_str(&format!("; Domain={domain}"));
+    }
+    if let Some(max_age) = cookie.max_age {
+        value.push_str(&format!("; Max-Age={max_age}"));
+    }
+    if cookie.secure {
+        value.push_str("; Secure");
+    }
+    if cookie.http_only {
+        value.push_str("; HttpOnly");
+    }
+    if let Some(same_site) = cookie.same_site {
+        value.push_str(&format!("; SameSite={}", same_site.as_str()));
+    }
+    value
+}
+
+/// Build literal response from `return` directive,
+/// no file system access and no cache headers.
+/// Status and content type are checked when config is loaded.
+///
+/// ## Arguments
+///
+/// `req`: client request, used for `$variable` expansion
+/// `res`: response builder with headers from config
+/// `ret`: return directive from config
+/// `peer_addr`: client socket address
+pub fn handle_return<T>(
+    req: &Request<T>,
+    res: Builder,
+    ret: &ReturnRoute,
+    peer_addr: &SocketAddr,
+) -> Result<Response<CandyBody<Bytes>>> {
+    let status = StatusCode::from_u16(ret.status)
+        .with_context(|| format!("invalid return status {}", ret.status))?;
+    let body = ret
+        .body
+        .as_ref()
+        .map(|body| expand_variables(body, req, peer_addr))
+        .unwrap_or_default();
+    let mut res = res.status(status);
+    if let Some(location) = &ret.location {
+        res = res.header("Location", expand_variables(location, req, peer_addr));
+    }
+    if !body.is_empty() {
+        let content_type = ret.content_type.as_deref().unwrap_or(TEXT_PLAIN);
+        res = res.header("Content-Type", content_type);
+    }
+    let body = Full::new(body.into()).map_err(|e| match e {}).boxed();
+    Ok(res.body(body)?)
+}
+
 // HTTP methods
 /// handle http get method
 /// read static file and check If-None-Match cache
-#[instrument(level = "debug")]
+#[instrument(level = "debug", skip(vfs))]
 pub async fn handle_get(
     req: Request<Incoming>,
     mut res: Builder,
     path: &str,
+    router: &SettingRoute,
+    vfs: &impl AsyncVfs,
 ) -> Result<Response<CandyBody<Bytes>>> {
-    use CompressType::*;
     use Error::*;
 
     let headers = res
         .headers_mut()
         .ok_or(InternalServerError(anyhow!("build response failed")))?;
 
-    // file bytes
-    let file = open_file(path).await?;
-    // file info
-    let metadata = file.metadata().await?;
-    let size = metadata.len();
-    let last_modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
-    let etag = format!("{last_modified}-{size}");
+    // file bytes and info
+    let VfsFile { etag, reader } = vfs.open(path, router.etag_mode).await?;
     let extension = PathBuf::from_str(path).map_err(|err| InternalServerError(anyhow!(err)))?;
     let extension = extension
         .extension()
@@ -308,11 +689,26 @@ pub async fn handle_get(
             .to_str()
             .ok_or(InternalServerError(anyhow!("read file extension failed")))?,
     );
-    headers.insert(
-        "Content-Type",
-        content_type.unwrap_or(&settings.default_type).parse()?,
-    );
+    let content_type = content_type.unwrap_or(&settings.default_type);
+    headers.insert("Content-Type", content_type.parse()?);
+    // every representation has its own ETag
+    let compression = &settings.compression;
+    let compressible = is_compressible(content_type, &compression.exclude_types);
+    let encoding = req
+        .headers()
+        .get("Accept-Encoding")
+        .and_then(|accept| accept.to_str().ok())
+        .filter(|_| compressible)
+        .and_then(|accept| CompressType::from_accept(accept, &compression.prefer));
+    debug!("response encoding {:?}", encoding);
+    let etag = match encoding {
+        Some(encoding) => encoded_etag(&etag, encoding),
+        None => etag,
+    };
     headers.insert("Etag", etag.parse()?);
+    if compressible {
+        headers.append("Vary", "Accept-Encoding".parse()?);
+    }
 
     // check cache
     let if_none_match = req.headers().get("If-None-Match");
@@ -325,34 +721,14 @@ pub async fn handle_get(
         _ => {}
     }
 
-    let file_reader = BufReader::new(file);
-    // prepare compress
-    let accept_encoding = req.headers().get("Accept-Encoding");
-    let boxed_body = match accept_encoding {
-        Some(accept) => {
-            let accept = accept.to_str()?;
-            debug!("Accept-Encoding {}", accept);
-            match accept {
-                str if str.contains("zstd") => {
-                    headers.insert("Content-Encoding", "zstd".parse()?);
-                    stream_compress(Zstd, file_reader)
-                }
-                str if str.contains("gzip") => {
-                    headers.insert("Content-Encoding", "gzip".parse()?);
-                    stream_compress(Gzip, file_reader)
-                }
-                str if str.contains("deflate") => {
-                    headers.insert("Content-Encoding", "deflate".parse()?);
-                    stream_compress(Deflate, file_reader)
-                }
-                str if str.contains("br") => {
-                    headers.insert("Content-Encoding", "br".parse()?);
-                    stream_compress(Brotli, file_reader)
-                }
-                _ => stream_file(file_reader).await,
-            }
+    let boxed_body = match encoding {
+        Some(encoding) => {
+            // compressed body is chunked
+            headers.remove("Content-Length");
+            headers.insert("Content-Encoding", encoding.name().parse()?);
+            stream_compress(encoding, reader)
         }
-        None => stream_file(file_reader).await,
+        None => stream_file(reader).await,
     };
 
     Ok(res.body(boxed_body)?)
@@ -363,17 +739,158 @@ pub async fn handle_not_found(
     res: Builder,
     router: &SettingRoute,
     assets_path: &str,
+    vfs: &impl AsyncVfs,
 ) -> Result<Response<CandyBody<Bytes>>> {
-    let res = if let Some(err_page) = &router.error_page {
-        let res = res.status(err_page.status);
-        if let Some(root) = &router.root {
+    let not_found_err = format!("resource {} not found", req.uri().path());
+    let res = match (&router.error_page, &router.root) {
+        (Some(err_page), Some(root)) => {
+            let res = res.status(err_page.status);
             let path = parse_assets_path(assets_path, root, &err_page.page);
-            handle_get(req, res, &path).await?
-        } else {
-            not_found()
+            handle_get(req, res, &path, router, vfs).await?
         }
-    } else {
-        not_found()
+        _ => return Err(Error::NotFound(not_found_err.into())),
     };
     Ok(res)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handle_return_works() {
+        let req = Request::builder()
+            .uri("/healthz")
+            .header("host", "localhost:4000")
+            .body(())
+            .unwrap();
+        let addr: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let ret = ReturnRoute {
+            status: 200,
+            body: Some("ok host=$host".into()),
+            content_type: None,
+            location: None,
+        };
+        let res = handle_return(&req, Response::builder(), &ret, &addr).unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["Content-Type"], "text/plain");
+        assert!(res.headers().get("Etag").is_none());
+        let body = futures_util::FutureExt::now_or_never(res.into_body().collect())
+            .unwrap()
+            .unwrap()
+            .to_bytes();
+        assert_eq!(body, "ok host=localhost");
+
+        // body of redirect is a body, target comes from location
+        let ret = ReturnRoute {
+            status: 302,
+            body: Some("Moved".into()),
+            content_type: None,
+            location: Some("https://$host/new".into()),
+        };
+        let res = handle_return(&req, Response::builder(), &ret, &addr).unwrap();
+        assert_eq!(res.status(), StatusCode::FOUND);
+        assert_eq!(res.headers()["Location"], "https://localhost/new");
+        let body = futures_util::FutureExt::now_or_never(res.into_body().collect())
+            .unwrap()
+            .unwrap()
+            .to_bytes();
+        assert_eq!(body, "Moved");
+    }
+
+    #[tokio::test]
+    async fn strong_etag_works() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let content = vec![b'a'; 200 * 1024];
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        std::fs::write(&a, &content).unwrap();
+        std::fs::write(&b, &content).unwrap();
+        let mut file_a = File::open(&a).await.unwrap();
+        let mut file_b = File::open(&b).await.unwrap();
+        let size = content.len() as u64;
+        let etag_a = strong_etag(&mut file_a, size).await.unwrap();
+        let etag_b = strong_etag(&mut file_b, size).await.unwrap();
+        assert_eq!(etag_a, etag_b);
+        assert!(etag_a.starts_with('"') && !etag_a.starts_with("W/"));
+    }
+
+    #[test]
+    fn error_response_works() {
+        let err = Error::UpstreamUnavailable("upstream down".into());
+        let res = error_response(&err, ErrorFormat::Json);
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers()["Content-Type"], APPLICATION_JSON);
+        let body = futures_util::FutureExt::now_or_never(res.into_body().collect())
+            .unwrap()
+            .unwrap()
+            .to_bytes();
+        assert_eq!(
+            body,
+            r#"{"error":"upstream_unavailable","message":"Service Unavailable"}"#
+        );
+        let res = error_response(&Error::NotFound("/".into()), ErrorFormat::Text);
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn set_cookie_value_works() {
+        let cookie = SettingCookie {
+            name: "session_host".into(),
+            value: "vh1".into(),
+            path: Some("/".into()),
+            domain: None,
+            max_age: Some(3600),
+            secure: false,
+            http_only: true,
+            same_site: Some(crate::config::SameSite::Lax),
+        };
+        assert_eq!(
+            set_cookie_value(&cookie),
+            "session_host=vh1; Path=/; Max-Age=3600; HttpOnly; SameSite=Lax"
+        );
+    }
+
+    #[test]
+    fn set_server_headers_works() {
+        let host = |tokens: &str| -> SettingHost {
+            toml::from_str(&format!(
+                "ip = \"0.0.0.0\"\nport = 4000\nroute = []\n{tokens}"
+            ))
+            .unwrap()
+        };
+        let upstream = || {
+            let mut headers = HeaderMap::new();
+            headers.insert("Server", "nginx".parse().unwrap());
+            headers
+        };
+
+        // on, upstream server is kept
+        let on = host("server_tokens = \"on\"");
+        let mut headers = HeaderMap::new();
+        set_server_headers(&mut headers, &on).unwrap();
+        assert_eq!(headers["Server"], NAME);
+        assert_eq!(headers["X-Candy-Version"], VERSION);
+        let mut headers = upstream();
+        set_server_headers(&mut headers, &on).unwrap();
+        assert_eq!(headers["Server"], "nginx");
+
+        // off, upstream server is removed
+        let off = host("server_tokens = \"off\"");
+        let mut headers = upstream();
+        set_server_headers(&mut headers, &off).unwrap();
+        assert!(headers.is_empty());
+
+        // custom, upstream server is replaced
+        let custom = host("server_tokens = \"custom:MyServer\"\nexpose_version = false");
+        let mut headers = upstream();
+        set_server_headers(&mut headers, &custom).unwrap();
+        assert_eq!(headers["Server"], "MyServer");
+        assert!(!headers.contains_key("X-Candy-Version"));
+
+        let invalid = "ip = \"0.0.0.0\"\nport = 4000\nroute = []\nserver_tokens = \"custom:\"";
+        assert!(toml::from_str::<SettingHost>(invalid).is_err());
+        let invalid = "ip = \"0.0.0.0\"\nport = 4000\nroute = []\nserver_tokens = \"custom:a\\nb\"";
+        assert!(toml::from_str::<SettingHost>(invalid).is_err());
+    }
+}
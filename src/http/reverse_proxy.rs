@@ -0,0 +1,139 @@
+use http::{header, HeaderMap, HeaderName, HeaderValue, Uri};
+
+use crate::{config::ProxyHostHeader, error::Result};
+
+/// Hop-by-hop headers only meaningful for a single transport-level connection
+/// https://datatracker.ietf.org/doc/html/rfc7230#section-6.1
+const HOP_BY_HOP_HEADERS: [HeaderName; 7] = [
+    header::CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    HeaderName::from_static("proxy-connection"),
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+/// Remove hop-by-hop headers before forward request to upstream
+/// or return upstream response to client
+///
+/// Headers listed in the `Connection` header value are removed as well.
+///
+/// ## Arguments
+///
+/// `headers`: request or response headers
+pub fn strip_hop_by_hop_headers(headers: &mut HeaderMap) {
+    let listed = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect::<Vec<_>>();
+    for name in listed.iter().chain(HOP_BY_HOP_HEADERS.iter()) {
+        headers.remove(name);
+    }
+}
+
+/// Set `Host` header of request to upstream
+///
+/// ## Arguments
+///
+/// `headers`: request headers copied from client
+/// `mode`: `proxy_host_header` of route
+/// `upstream`: upstream url of this request
+/// `client_uri`: client request uri, its authority is the host of HTTP/2 requests
+pub fn set_host_header(
+    headers: &mut HeaderMap,
+    mode: &ProxyHostHeader,
+    upstream: &Uri,
+    client_uri: &Uri,
+) -> Result<()> {
+    let host = match mode {
+        ProxyHostHeader::Preserve if headers.contains_key(header::HOST) => return Ok(()),
+        ProxyHostHeader::Preserve => match client_uri.authority() {
+            Some(authority) => authority.as_str(),
+            None => return Ok(()),
+        },
+        ProxyHostHeader::Upstream => match upstream.authority() {
+            Some(authority) => authority.as_str(),
+            None => return Ok(()),
+        },
+        ProxyHostHeader::Custom(host) => host,
+    };
+    // strip userinfo of authority
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+    headers.insert(header::HOST, HeaderValue::from_str(host)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_hop_by_hop_headers_works() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Transfer-Encoding", "chunked".parse().unwrap());
+        headers.insert("Connection", "keep-alive, X-Trace".parse().unwrap());
+        headers.append("Connection", "Upgrade".parse().unwrap());
+        headers.insert("Keep-Alive", "timeout=5".parse().unwrap());
+        headers.insert("Upgrade", "websocket".parse().unwrap());
+        headers.insert("TE", "trailers".parse().unwrap());
+        headers.insert("X-Trace", "1".parse().unwrap());
+        headers.insert("Content-Type", "text/plain".parse().unwrap());
+        strip_hop_by_hop_headers(&mut headers);
+        assert!(!headers.contains_key("Transfer-Encoding"));
+        assert!(!headers.contains_key("X-Trace"));
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers["Content-Type"], "text/plain");
+    }
+
+    #[test]
+    fn set_host_header_works() {
+        let upstream: Uri = "http://internal-svc:8080/api".parse().unwrap();
+        let client_uri: Uri = "/api".parse().unwrap();
+        let host = |mode: ProxyHostHeader, client_host: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(client_host) = client_host {
+                headers.insert("Host", client_host.parse().unwrap());
+            }
+            set_host_header(&mut headers, &mode, &upstream, &client_uri).unwrap();
+            headers
+                .get("Host")
+                .map(|host| host.to_str().unwrap().to_string())
+        };
+        let client = Some("example.com");
+        assert_eq!(
+            host(ProxyHostHeader::Preserve, client).unwrap(),
+            "example.com"
+        );
+        assert_eq!(
+            host(ProxyHostHeader::Upstream, client).unwrap(),
+            "internal-svc:8080"
+        );
+        assert_eq!(
+            host(ProxyHostHeader::Custom("api.internal".into()), client).unwrap(),
+            "api.internal"
+        );
+        assert_eq!(host(ProxyHostHeader::Preserve, None), None);
+
+        // HTTP/2 clients send authority instead of Host
+        let mut headers = HeaderMap::new();
+        let client_uri: Uri = "https://example.com/api".parse().unwrap();
+        set_host_header(
+            &mut headers,
+            &ProxyHostHeader::Preserve,
+            &upstream,
+            &client_uri,
+        )
+        .unwrap();
+        assert_eq!(headers["Host"], "example.com");
+
+        assert!(ProxyHostHeader::try_from("custom:".to_string()).is_err());
+        assert_eq!(
+            ProxyHostHeader::try_from("preserve".to_string()).unwrap(),
+            ProxyHostHeader::Preserve
+        );
+    }
+}
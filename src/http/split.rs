@@ -0,0 +1,272 @@
+use std::{
+    hash::{BuildHasher, RandomState},
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::anyhow;
+use http::HeaderMap;
+use serde::Serialize;
+
+use crate::{config::SettingSplit, error::Result};
+
+/// Part of request hashed to choose the bucket
+#[derive(Clone, Debug)]
+pub enum SplitKey {
+    /// Cookie value by name
+    Cookie(String),
+    /// Request header value by name
+    Header(String),
+}
+
+/// 64-bit FNV-1a hash of split key value, stable across builds and restarts
+/// so clients stick to the same upstream after an upgrade
+/// http://www.isthe.com/chongo/tech/comp/fnv/
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// All weights zero would send every request to the first bucket
+fn check_weights(weights: impl Iterator<Item = u32>) -> Result<()> {
+    if weights.map(u64::from).sum::<u64>() == 0 {
+        return Err(anyhow!("split weights sum to 0").into());
+    }
+    Ok(())
+}
+
+/// Upstream bucket of traffic split
+#[derive(Debug)]
+struct Bucket {
+    upstream: String,
+    weight: AtomicU32,
+    hits: AtomicU64,
+}
+
+/// Bucket status in admin API
+#[derive(Serialize, Debug)]
+pub struct BucketStatus<'a> {
+    pub upstream: &'a str,
+    pub weight: u32,
+    pub hits: u64,
+}
+
+/// Weighted traffic split between upstreams compiled from config
+/// Weights can be changed at runtime from the admin API
+#[derive(Clone, Debug)]
+pub struct Splitter {
+    buckets: Arc<[Bucket]>,
+    /// Choose randomly when none or the request has no key
+    key: Option<SplitKey>,
+    random: RandomState,
+    counter: Arc<AtomicU64>,
+}
+
+impl Splitter {
+    /// Compile split from config
+    ///
+    /// ## Arguments
+    ///
+    /// `split`: upstream buckets from config file
+    /// `key`: `cookie:Name` or `header:Name`
+    pub fn new(split: &[SettingSplit], key: Option<&str>) -> Result<Self> {
+        if split.is_empty() {
+            return Err(anyhow!("split requires at least one upstream").into());
+        }
+        check_weights(split.iter().map(|bucket| bucket.weight))?;
+        let key = match key {
+            None => None,
+            Some(key) => match key.split_once(':') {
+                Some(("cookie", name)) if !name.is_empty() => {
+                    Some(SplitKey::Cookie(name.to_string()))
+                }
+                Some(("header", name)) if !name.is_empty() => {
+                    Some(SplitKey::Header(name.to_string()))
+                }
+                _ => return Err(anyhow!("split invalid key {key}").into()),
+            },
+        };
+        let buckets = split
+            .iter()
+            .map(|bucket| Bucket {
+                upstream: bucket.upstream.clone(),
+                weight: AtomicU32::new(bucket.weight),
+                hits: AtomicU64::new(0),
+            })
+            .collect();
+        Ok(Self {
+            buckets,
+            key,
+            random: RandomState::new(),
+            counter: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// Value of split key in request
+    fn key_value<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        match self.key.as_ref()? {
+            SplitKey::Header(name) => headers.get(name)?.to_str().ok(),
+            SplitKey::Cookie(name) => headers
+                .get_all("Cookie")
+                .iter()
+                .filter_map(|cookie| cookie.to_str().ok())
+                .flat_map(|cookie| cookie.split(';'))
+                .filter_map(|pair| pair.trim().split_once('='))
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value),
+        }
+    }
+
+    /// Choose upstream for request
+    /// The same key value always goes to the same bucket while weights are unchanged
+    ///
+    /// ## Arguments
+    ///
+    /// `headers`: client request headers
+    pub fn choose(&self, headers: &HeaderMap) -> &str {
+        let weights = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.weight.load(Ordering::Relaxed) as u64)
+            .collect::<Vec<_>>();
+        let total = weights.iter().sum::<u64>();
+        let hash = match self.key_value(headers) {
+            Some(value) => fnv1a(value.as_bytes()),
+            None => self
+                .random
+                .hash_one(self.counter.fetch_add(1, Ordering::Relaxed)),
+        };
+        // weights are checked to sum above 0 on compile and update
+        let mut point = hash % total;
+        let bucket = self
+            .buckets
+            .iter()
+            .zip(weights)
+            .find(|(_, weight)| {
+                if point < *weight {
+                    return true;
+                }
+                point -= weight;
+                false
+            })
+            .map_or(&self.buckets[0], |(bucket, _)| bucket);
+        bucket.hits.fetch_add(1, Ordering::Relaxed);
+        &bucket.upstream
+    }
+
+    /// Replace weights of buckets by order
+    pub fn set_weights(&self, weights: &[u32]) -> Result<()> {
+        if weights.len() != self.buckets.len() {
+            return Err(anyhow!(
+                "split expects {} weights, got {}",
+                self.buckets.len(),
+                weights.len()
+            )
+            .into());
+        }
+        check_weights(weights.iter().copied())?;
+        for (bucket, weight) in self.buckets.iter().zip(weights) {
+            bucket.weight.store(*weight, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Weight and requests count of each bucket
+    pub fn status(&self) -> Vec<BucketStatus<'_>> {
+        self.buckets
+            .iter()
+            .map(|bucket| BucketStatus {
+                upstream: &bucket.upstream,
+                weight: bucket.weight.load(Ordering::Relaxed),
+                hits: bucket.hits.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn splitter(key: Option<&str>) -> Splitter {
+        let split = [
+            SettingSplit {
+                upstream: "http://stable".into(),
+                weight: 95,
+            },
+            SettingSplit {
+                upstream: "http://canary".into(),
+                weight: 5,
+            },
+        ];
+        Splitter::new(&split, key).unwrap()
+    }
+
+    fn canary_ratio(splitter: &Splitter) -> f64 {
+        let status = splitter.status();
+        status[1].hits as f64 / (status[0].hits + status[1].hits) as f64
+    }
+
+    #[test]
+    fn split_random_works() {
+        let splitter = splitter(None);
+        let headers = HeaderMap::new();
+        for _ in 0..10_000 {
+            splitter.choose(&headers);
+        }
+        assert!((canary_ratio(&splitter) - 0.05).abs() < 0.01);
+
+        splitter.set_weights(&[0, 100]).unwrap();
+        assert_eq!(splitter.choose(&headers), "http://canary");
+        assert!(splitter.set_weights(&[1]).is_err());
+        assert!(splitter.set_weights(&[0, 0]).is_err());
+        assert_eq!(splitter.choose(&headers), "http://canary");
+    }
+
+    #[test]
+    fn fnv1a_works() {
+        // test vectors from the FNV reference
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn split_cookie_works() {
+        let splitter = splitter(Some("cookie:uid"));
+        let req = |uid: &str| {
+            let mut headers = HeaderMap::new();
+            let cookie = format!("theme=dark; uid={uid}");
+            headers.insert("Cookie", cookie.parse().unwrap());
+            headers
+        };
+        for i in 0..10_000 {
+            splitter.choose(&req(&format!("user-{i}")));
+        }
+        assert!((canary_ratio(&splitter) - 0.05).abs() < 0.01);
+
+        // the same user sticks to one side
+        let user = req("user-42");
+        let upstream = splitter.choose(&user).to_string();
+        for _ in 0..100 {
+            assert_eq!(splitter.choose(&user), upstream);
+        }
+        assert!(Splitter::new(&[], None).is_err());
+        let zero = SettingSplit {
+            upstream: "http://stable".into(),
+            weight: 0,
+        };
+        assert!(Splitter::new(&[zero], None).is_err());
+        assert!(Splitter::new(
+            &[SettingSplit {
+                upstream: "http://stable".into(),
+                weight: 1
+            }],
+            Some("query:uid")
+        )
+        .is_err());
+    }
+}
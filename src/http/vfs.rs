@@ -0,0 +1,135 @@
+use std::{future::Future, time::UNIX_EPOCH};
+
+use tokio::io::{AsyncBufRead, BufReader};
+
+use crate::{
+    config::EtagMode,
+    error::Result,
+    http::{open_file, strong_etag, ETAG_STRONG_MAX_SIZE},
+};
+
+/// Root prefix of static files embedded in binary, like `embed://site`
+pub const EMBED_PREFIX: &str = "embed://";
+
+/// Opened static file
+pub struct VfsFile {
+    pub etag: String,
+    pub reader: Box<dyn AsyncBufRead + Send + Sync + Unpin>,
+}
+
+/// Filesystem of static files
+pub trait AsyncVfs: Sync {
+    /// Check the path is a regular file
+    fn is_file(&self, path: &str) -> impl Future<Output = bool> + Send;
+
+    /// Open file for response
+    ///
+    /// ## Arguments
+    ///
+    /// `path`: file path resolved from route root
+    /// `etag_mode`: ETag generate mode of route
+    fn open(&self, path: &str, etag_mode: EtagMode)
+        -> impl Future<Output = Result<VfsFile>> + Send;
+}
+
+/// Local filesystem by tokio
+pub struct TokioFs;
+
+impl AsyncVfs for TokioFs {
+    async fn is_file(&self, path: &str) -> bool {
+        tokio::fs::metadata(path)
+            .await
+            .is_ok_and(|metadata| metadata.is_file())
+    }
+
+    async fn open(&self, path: &str, etag_mode: EtagMode) -> Result<VfsFile> {
+        let mut file = open_file(path).await?;
+        let metadata = file.metadata().await?;
+        let size = metadata.len();
+        let last_modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
+        let etag = match etag_mode {
+            EtagMode::Strong if size <= ETAG_STRONG_MAX_SIZE => {
+                strong_etag(&mut file, size).await?
+            }
+            _ => format!("{last_modified}-{size}"),
+        };
+        Ok(VfsFile {
+            etag,
+            reader: Box::new(BufReader::new(file)),
+        })
+    }
+}
+
+/// Static files compiled into binary from the `embed` directory,
+/// file contents never change so the ETag is always the content hash
+#[cfg(feature = "embed")]
+pub struct EmbedFs(pub &'static include_dir::Dir<'static>);
+
+#[cfg(feature = "embed")]
+pub static EMBED_DIR: include_dir::Dir<'static> =
+    include_dir::include_dir!("$CARGO_MANIFEST_DIR/embed");
+
+#[cfg(feature = "embed")]
+impl EmbedFs {
+    fn get(&self, path: &str) -> Option<&'static include_dir::File<'static>> {
+        let path = path.strip_prefix(EMBED_PREFIX)?;
+        self.0.get_file(path)
+    }
+}
+
+#[cfg(feature = "embed")]
+impl AsyncVfs for EmbedFs {
+    async fn is_file(&self, path: &str) -> bool {
+        self.get(path).is_some()
+    }
+
+    async fn open(&self, path: &str, _etag_mode: EtagMode) -> Result<VfsFile> {
+        use sha2::{Digest, Sha256};
+
+        let file = self.get(path).ok_or_else(|| {
+            crate::error::Error::NotFound(format!("path not found {path}").into())
+        })?;
+        let contents = file.contents();
+        Ok(VfsFile {
+            etag: format!("\"{:x}\"", Sha256::digest(contents)),
+            reader: Box::new(contents),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    async fn check_vfs(vfs: &impl AsyncVfs, root: &str) {
+        let index = format!("{root}/index.html");
+        assert!(vfs.is_file(&index).await);
+        assert!(!vfs.is_file(root).await);
+        assert!(!vfs.is_file(&format!("{root}/missing.html")).await);
+        assert!(vfs
+            .open(&format!("{root}/missing.html"), EtagMode::Strong)
+            .await
+            .is_err());
+
+        let mut file = vfs.open(&index, EtagMode::Strong).await.unwrap();
+        let mut contents = vec![];
+        file.reader.read_to_end(&mut contents).await.unwrap();
+        assert!(String::from_utf8(contents).unwrap().contains("<html"));
+        let again = vfs.open(&index, EtagMode::Strong).await.unwrap();
+        assert_eq!(file.etag, again.etag);
+        assert!(file.etag.starts_with('"'));
+    }
+
+    #[tokio::test]
+    async fn tokio_fs_works() {
+        check_vfs(&TokioFs, concat!(env!("CARGO_MANIFEST_DIR"), "/embed/site")).await;
+    }
+
+    #[cfg(feature = "embed")]
+    #[tokio::test]
+    async fn embed_fs_works() {
+        check_vfs(&EmbedFs(&EMBED_DIR), "embed://site").await;
+    }
+}
@@ -1,16 +1,25 @@
+use std::{collections::BTreeMap, net::ToSocketAddrs, sync::LazyLock};
+
 use anyhow::{anyhow, Context, Result};
 
 use clap::Parser;
 use config::Settings;
 use consts::COMPILER;
 use tokio::task::JoinSet;
-use tracing::{debug, info};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
 
 use crate::{
-    consts::{get_settings, ARCH, NAME, OS, SETTINGS, VERSION},
-    utils::init_logger,
+    consts::{get_settings, ARCH, NAME, OS, SETTINGS, START_TIME, VERSION},
+    service::Shutdown,
+    utils::{
+        geoip::init_geoip,
+        init_logger,
+        listenfd::{listen_fds, take_listener},
+    },
 };
 
+mod admin;
 mod cli;
 mod config;
 mod consts;
@@ -21,9 +30,24 @@ mod utils;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    LazyLock::force(&START_TIME);
     let args = cli::Cli::parse();
-    init_logger();
     let settings = Settings::new(&args.config).with_context(|| "init config failed")?;
+    // before logger and geoip, dry run has no side effects
+    if args.dry_run {
+        dry_run(&settings)?;
+        return Ok(());
+    }
+    // dropped after servers stopped, flushes file logs
+    let log_guard = init_logger(
+        args.log_level.as_deref(),
+        settings.log_level.as_deref(),
+        settings.log_backend,
+        settings.log_file.as_deref(),
+    )?;
+    for warning in &settings.warnings {
+        warn!("config {warning}");
+    }
     SETTINGS
         .set(settings)
         .map_err(|err| anyhow!("init config failed {err:?}"))?;
@@ -34,17 +58,261 @@ async fn main() -> Result<()> {
     info!("{}/{} {}", NAME, VERSION, COMPILER);
     info!("OS: {} {}", OS, ARCH);
 
-    let mut servers = settings
+    if let Some(geoip) = &settings.geoip {
+        init_geoip(geoip)?;
+        info!("geoip database {} opened", geoip.database);
+    }
+
+    // adopt listeners from binary upgrade or systemd socket activation
+    let mut inherited = listen_fds();
+    let shutdown = Shutdown::default();
+    let listeners = settings
         .host
         .iter()
-        .map(|host| host.mk_server())
+        .map(|host| take_listener(&host.addr(), &mut inherited.listeners).map(|l| (host, l)))
+        .collect::<Result<Vec<_>>>()?;
+    let admin = settings
+        .admin
+        .as_ref()
+        .map(|admin| admin.listener(&mut inherited.listeners).map(|l| (admin, l)))
+        .transpose()?;
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsRawFd;
+        let fds = listeners
+            .iter()
+            .map(|(_, l)| l.as_raw_fd())
+            .chain(admin.iter().map(|(_, l)| l.as_raw_fd()))
+            .collect();
+        tokio::spawn(upgrade_on_signal(
+            args.config.clone(),
+            fds,
+            shutdown.handover.clone(),
+        ));
+    }
+    let signals = tokio::spawn(shutdown_on_signal(shutdown.terminate.clone()));
+    let mut servers = listeners
+        .into_iter()
+        .map(|(host, listener)| host.mk_server(listener, shutdown.clone()))
         .collect::<JoinSet<_>>();
+    if let Some((admin, listener)) = admin {
+        servers.spawn(admin.mk_server(listener, shutdown.clone()));
+    }
+    // parent process of binary upgrade starts draining after it
+    inherited.notify_ready();
 
     info!("server started");
 
-    while let Some(res) = servers.join_next().await {
-        res??;
+    let stopped = async {
+        while let Some(res) = servers.join_next().await {
+            res??;
+        }
+        anyhow::Ok(())
+    };
+    tokio::select! {
+        res = stopped => res?,
+        // second SIGTERM or SIGINT, don't wait in-flight requests
+        _ = signals => {
+            drop(log_guard);
+            std::process::exit(1);
+        }
     }
+    info!("server stopped");
+    drop(log_guard);
 
     Ok(())
 }
+
+/// Check everything up to binding ports and print routes of each host to stdout
+///
+/// ## Arguments
+///
+/// `settings`: loaded config
+///
+/// ## Return
+///
+/// number of routes registered on all hosts
+fn dry_run(settings: &Settings) -> Result<usize> {
+    for warning in &settings.warnings {
+        eprintln!("config {warning}");
+    }
+    // two listeners on one address fail at bind
+    let mut bound = BTreeMap::new();
+    let mut total = 0;
+    for host in &settings.host {
+        for addr in host.dry_run()? {
+            if let Some(other) = bound.insert(addr, host.addr()) {
+                return Err(anyhow!(
+                    "host {} and host {other} both listen on {addr}",
+                    host.addr()
+                ));
+            }
+        }
+        println!("{} {} routes", host.addr(), host.route_map.len());
+        total += host.route_map.len();
+    }
+    if let Some(admin) = &settings.admin {
+        let addrs = admin
+            .listen
+            .to_socket_addrs()
+            .with_context(|| format!("invalid admin address {}", admin.listen))?;
+        for addr in addrs {
+            if let Some(other) = bound.insert(addr, admin.listen.clone()) {
+                return Err(anyhow!("admin and host {other} both listen on {addr}"));
+            }
+        }
+    }
+    println!("config ok, {total} routes registered");
+    Ok(total)
+}
+
+/// Stop accepting connections on `SIGTERM` or `SIGINT`,
+/// servers return after in-flight requests finished
+///
+/// Returns on the second signal, the process exits without waiting
+///
+/// ## Arguments
+///
+/// `shutdown`: terminate token, cancel to drain and stop all servers
+async fn shutdown_on_signal(shutdown: CancellationToken) {
+    #[cfg(unix)]
+    let mut sigterm = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+    {
+        Ok(sigterm) => Some(sigterm),
+        Err(err) => {
+            tracing::error!("listen SIGTERM failed {err}");
+            None
+        }
+    };
+
+    for forced in [false, true] {
+        #[cfg(unix)]
+        let terminate = async {
+            match sigterm.as_mut() {
+                Some(sigterm) => sigterm.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        #[cfg(not(unix))]
+        let terminate = std::future::pending::<Option<()>>();
+
+        let name = tokio::select! {
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+            _ = terminate => "SIGTERM",
+        };
+        if forced {
+            warn!("{name} received again, exit immediately");
+            return;
+        }
+        info!("{name} received, starting shutdown");
+        shutdown.cancel();
+    }
+}
+
+/// Spawn new process of current binary on `SIGUSR2`,
+/// then hand over listeners and drain current process once the new one is ready
+///
+/// The config file is checked first, an invalid config keeps current process serving
+///
+/// ## Arguments
+///
+/// `config`: config file path of new process
+/// `fds`: raw file descriptors of host and admin listeners
+/// `handover`: cancel to stop accepting on current process
+#[cfg(unix)]
+async fn upgrade_on_signal(
+    config: String,
+    fds: Vec<std::os::fd::RawFd>,
+    handover: CancellationToken,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut usr2 = match signal(SignalKind::user_defined2()) {
+        Ok(usr2) => usr2,
+        Err(err) => {
+            tracing::error!("listen SIGUSR2 failed {err}");
+            return;
+        }
+    };
+    while usr2.recv().await.is_some() {
+        if let Err(err) = Settings::new(&config) {
+            tracing::error!("config {config} invalid, binary upgrade refused {err}");
+            continue;
+        }
+        let upgrade = match utils::listenfd::spawn_upgrade(&fds) {
+            Ok(upgrade) => upgrade,
+            Err(err) => {
+                tracing::error!("binary upgrade failed {err:?}");
+                continue;
+            }
+        };
+        // keep serving until the new process bound all listeners
+        match upgrade.wait_ready().await {
+            Ok(pid) => {
+                info!("SIGUSR2 received, new process {pid} ready");
+                handover.cancel();
+                break;
+            }
+            Err(err) => tracing::error!("binary upgrade failed {err:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn dry_run_works() {
+        let load = |text: &str| {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("config.toml");
+            std::fs::write(&path, text).unwrap();
+            Settings::new(path.to_str().unwrap()).unwrap()
+        };
+        let host = |port: u16| {
+            format!(
+                "[[host]]\nip = \"127.0.0.1\"\nport = {port}\n[[host.route]]\nlocation = \"/\"\nreturn = {{ status = 200 }}\n"
+            )
+        };
+        let settings = load(&format!("{}{}", host(4000), host(4001)));
+        assert_eq!(dry_run(&settings).unwrap(), 2);
+
+        let settings = load(&format!("{}{}", host(4000), host(4000)));
+        let err = dry_run(&settings).unwrap_err();
+        assert!(err.to_string().contains("both listen on 127.0.0.1:4000"));
+
+        let admin = "[admin]\nlisten = \"127.0.0.1:4000\"\ntoken = \"secret\"\n";
+        let settings = load(&format!("{admin}{}", host(4000)));
+        assert!(dry_run(&settings).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn shutdown_on_signal_works() {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        // keep the process alive if SIGTERM arrives before the task listens
+        let _guard = signal(SignalKind::terminate()).unwrap();
+        let shutdown = CancellationToken::new();
+        let task = tokio::spawn(shutdown_on_signal(shutdown.clone()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // SAFETY: SIGTERM is handled by tokio signal driver
+        unsafe { libc::raise(libc::SIGTERM) };
+        tokio::time::timeout(Duration::from_secs(5), shutdown.cancelled())
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!task.is_finished());
+
+        // second signal forces exit
+        unsafe { libc::raise(libc::SIGTERM) };
+        tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
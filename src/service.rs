@@ -1,18 +1,23 @@
 use std::{
-    net::SocketAddr,
-    pin::pin,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::atomic::Ordering,
     time::{self, Duration},
 };
 
 use crate::{
-    config::SettingHost,
+    config::{ErrorFormat, LimitConnKey, SettingHost},
     error::Error,
-    http::{internal_server_error, not_found, CandyHandler},
+    http::{
+        error_format, error_response, filter::check_filters, health::health_response,
+        set_host_headers, CandyBody, CandyHandler,
+    },
+    utils::{geoip::country_code, limit::guard_body, normalize::normalize_request},
 };
 
+use anyhow::{anyhow, Context};
 use futures_util::Future;
-use http::Request;
-use hyper::body::Incoming;
+use http::{Request, Response, StatusCode};
+use hyper::body::{Bytes, Incoming};
 use hyper_util::{
     rt::{TokioExecutor, TokioIo},
     server::{self, graceful::GracefulShutdown},
@@ -22,46 +27,115 @@ use tokio::{
     select,
 };
 
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 
+/// Stop signals of servers
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown {
+    /// Cancelled on SIGTERM or SIGINT,
+    /// hosts keep accepting for `shutdown_delay` while the health endpoint answers 503
+    pub terminate: CancellationToken,
+    /// Cancelled once the new process of binary upgrade is ready,
+    /// hosts stop accepting at once and stay healthy
+    pub handover: CancellationToken,
+}
+
+impl Shutdown {
+    /// Wait until terminate or handover
+    pub async fn cancelled(&self) {
+        select! {
+            _ = self.terminate.cancelled() => {}
+            _ = self.handover.cancelled() => {}
+        }
+    }
+}
+
 impl SettingHost {
-    pub fn mk_server(&'static self) -> impl Future<Output = anyhow::Result<()>> + 'static {
-        let addr = format!("{}:{}", self.ip, self.port);
+    /// Check host address without binding port
+    ///
+    /// ## Return
+    ///
+    /// socket addresses the host would bind
+    pub fn dry_run(&self) -> anyhow::Result<Vec<SocketAddr>> {
+        let addr = self.addr();
+        let addrs = addr
+            .to_socket_addrs()
+            .with_context(|| format!("invalid host address {addr}"))?;
+        Ok(addrs.collect())
+    }
+
+    /// Host listen address
+    pub fn addr(&self) -> String {
+        format!("{}:{}", self.ip, self.port)
+    }
+
+    /// Serve host on listener until terminated and `shutdown_delay` passed, or handed over,
+    /// then wait in-flight requests up to `shutdown_timeout`
+    ///
+    /// ## Arguments
+    ///
+    /// `listener`: bound or inherited listener of host address
+    /// `shutdown`: stop signals of SIGTERM, SIGINT or binary upgrade
+    pub fn mk_server(
+        &'static self,
+        listener: std::net::TcpListener,
+        shutdown: Shutdown,
+    ) -> impl Future<Output = anyhow::Result<()>> + 'static {
+        let addr = self.addr();
         async move {
-            let listener = TcpListener::bind(&addr).await?;
+            let listener = TcpListener::from_std(listener)?;
             info!("host bind on {}", addr);
 
-            let server = server::conn::auto::Builder::new(hyper_util::rt::TokioExecutor::new());
+            let mut server = server::conn::auto::Builder::new(hyper_util::rt::TokioExecutor::new());
+            server
+                .http1()
+                .timer(hyper_util::rt::TokioTimer::new())
+                .header_read_timeout(self.timeout.0);
             let graceful = server::graceful::GracefulShutdown::new();
-            let mut ctrl_c = pin!(tokio::signal::ctrl_c());
 
-            loop {
-                tokio::select! {
-                    conn = listener.accept() => {
-                        let conn = match conn {
-                            Ok(conn) => conn,
-                            Err(e) => {
-                                error!("accept error: {}", e);
-                                continue;
-                            }
-                        };
-                        handle_connection(conn, self, &server, &graceful).await;
-                    },
-
-                    _ = ctrl_c.as_mut() => {
-                        drop(listener);
-                        info!("Ctrl-C received, starting shutdown");
-                        break;
+            let accept = async {
+                loop {
+                    let conn = match listener.accept().await {
+                        Ok(conn) => conn,
+                        Err(e) => {
+                            error!("accept error: {}", e);
+                            continue;
+                        }
+                    };
+                    handle_connection(conn, self, &server, &graceful).await;
+                }
+            };
+            let draining = async {
+                select! {
+                    // keep accepting for shutdown_delay while health endpoint answers 503
+                    _ = shutdown.terminate.cancelled() => {
+                        self.draining.store(true, Ordering::Relaxed);
+                        info!("host {} draining, starting shutdown", addr);
+                        tokio::time::sleep(self.shutdown_delay.0).await;
+                    }
+                    // new process accepts on the same listener already
+                    _ = shutdown.handover.cancelled() => {
+                        info!("host {} handed over to new process", addr);
                     }
                 }
+            };
+            select! {
+                _ = accept => {}
+                _ = draining => {}
             }
+            drop(listener);
+            info!("host {} stopped accepting", addr);
 
             select! {
                 _ = graceful.shutdown() => {
                     info!("Gracefully shutdown!");
                 },
-                _ = tokio::time::sleep(Duration::from_secs(self.timeout.into())) => {
-                    error!("Waited 10 seconds for graceful shutdown, aborting...");
+                _ = tokio::time::sleep(self.shutdown_timeout.0) => {
+                    error!(
+                        "Waited {:?} for graceful shutdown, aborting...",
+                        self.shutdown_timeout.0
+                    );
                 }
             }
             Ok(())
@@ -69,6 +143,41 @@ impl SettingHost {
     }
 }
 
+/// Error response of host rejected before routing, with host headers
+///
+/// ## Arguments
+///
+/// `host`: host from config file
+/// `err`: reason of rejection
+/// `format`: response body format
+fn host_error_response(
+    host: &'static SettingHost,
+    err: &Error,
+    format: ErrorFormat,
+) -> Response<CandyBody<Bytes>> {
+    let mut response = error_response(err, format);
+    if let Err(err) = set_host_headers(response.headers_mut(), host) {
+        error!("add headers to response failed {err}");
+    }
+    response
+}
+
+/// Format request latency for access log
+///
+/// ## Arguments
+///
+/// `elapsed`: duration from request received to response built
+pub fn format_latency(elapsed: Duration) -> String {
+    let nanos = elapsed.as_nanos();
+    if nanos < 1_000 {
+        format!("{nanos}ns")
+    } else if nanos < 1_000_000 {
+        format!("{}μs", elapsed.as_micros())
+    } else {
+        format!("{}ms", elapsed.as_millis())
+    }
+}
+
 /// Handle tcp connection from client
 /// then use hyper service to handle response
 ///
@@ -89,13 +198,73 @@ async fn handle_connection(
 
     let stream = TokioIo::new(Box::pin(stream));
 
-    let service = move |req: Request<Incoming>| async move {
+    let service = move |mut req: Request<Incoming>| async move {
         let start_time = time::Instant::now();
+        let format = error_format(host, req.headers().get("Accept"));
+        // canonical path for filters, routing, proxy and access log
+        if let Err(err) = normalize_request(&mut req, host.merge_slashes) {
+            warn!("\"{peer_addr}\" {} {err}", req.uri());
+            return anyhow::Ok(error_response(&err, format));
+        }
         let method = req.method().clone();
         let uri = req.uri().clone();
         let path = uri.path();
         let version = req.version();
-        let mut handler = CandyHandler::new(req, host);
+        // built-in health check, answered before filters and routing
+        if host.health_endpoint.as_deref() == Some(path) {
+            let response = health_response(host);
+            if host.health_access_log {
+                info!(
+                    "\"{peer_addr}\" {method} {path} {version:?} {}",
+                    response.status()
+                );
+            }
+            return anyhow::Ok(response);
+        }
+        // maintenance mode from admin API
+        if host.maintenance.load(Ordering::Relaxed) {
+            let response = host_error_response(host, &Error::Maintenance, format);
+            info!(
+                "\"{peer_addr}\" {method} {path} {version:?} {} maintenance",
+                response.status()
+            );
+            return anyhow::Ok(response);
+        }
+        // request filters
+        if let Some(filter) = check_filters(&host.filters, &req) {
+            if filter.status == 444 {
+                info!("\"{peer_addr}\" {method} {path} {version:?} 444 dropped");
+                return Err(anyhow!("request denied by filter {}", filter.id));
+            }
+            // checked to be 400-599 when config is loaded
+            let status = StatusCode::from_u16(filter.status).unwrap_or(StatusCode::FORBIDDEN);
+            let response = host_error_response(host, &Error::Rejected(status), format);
+            info!(
+                "\"{peer_addr}\" {method} {path} {version:?} {}",
+                response.status()
+            );
+            return anyhow::Ok(response);
+        }
+        // in-flight requests of client
+        let guard = match &host.limit_conn {
+            Some(limit) => {
+                let key = match limit.key {
+                    LimitConnKey::Ip => peer_addr.ip(),
+                };
+                let Some(guard) = host.conn_limiter.acquire(key, limit.max) else {
+                    // checked to be 400-599 when config is loaded
+                    let status = StatusCode::from_u16(limit.status)
+                        .unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+                    let err = Error::Rejected(status);
+                    let response = host_error_response(host, &err, format);
+                    warn!("\"{peer_addr}\" {method} {path} rejected by limit_conn");
+                    return anyhow::Ok(response);
+                };
+                Some(guard)
+            }
+            None => None,
+        };
+        let mut handler = CandyHandler::new(req, host, peer_addr);
         // Connection handler in service_fn
         // then decide whether to handle proxy or static file based on config
         let _ = handler
@@ -104,25 +273,27 @@ async fn handle_connection(
         let res = handler.handle().await;
         let response = match res {
             Ok(res) => res,
-            Err(Error::NotFound(err)) => {
+            Err(err @ (Error::NotFound(_) | Error::MethodNotAllowed(_))) => {
                 warn!("{err}");
-                not_found()
+                error_response(&err, format)
             }
             Err(err) => {
                 error!("{err}");
-                internal_server_error()
+                error_response(&err, format)
             }
         };
-        let instant_elapsed = start_time.elapsed();
-        let micros = instant_elapsed.as_micros();
-        let millis = instant_elapsed.as_millis();
-        let end_time = if micros > 1000 {
-            format!("{millis:.3}ms")
-        } else {
-            format!("{micros:.3}μs")
+        let response = match guard {
+            Some(guard) => response.map(|body| guard_body(body, guard)),
+            None => response,
         };
+        let end_time = format_latency(start_time.elapsed());
         let res_status = response.status();
-        info!("\"{peer_addr}\" {method} {path} {version:?} {res_status} {end_time}");
+        match country_code(peer_addr.ip()) {
+            "" => info!("\"{peer_addr}\" {method} {path} {version:?} {res_status} {end_time}"),
+            country => info!(
+                "\"{peer_addr}\" {country} {method} {path} {version:?} {res_status} {end_time}"
+            ),
+        }
         anyhow::Ok(response)
     };
 
@@ -136,3 +307,505 @@ async fn handle_connection(
         debug!("connection dropped: {}", peer_addr);
     });
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Global settings used by static file routes
+    pub(crate) fn init_settings() {
+        crate::consts::SETTINGS.get_or_init(|| {
+            let mut settings: crate::config::Settings = toml::from_str("host = []").unwrap();
+            crate::consts::insert_default_mimes(&mut settings.types);
+            settings
+        });
+    }
+
+    /// Roots of the same static site on every filesystem backend
+    fn static_roots() -> Vec<&'static str> {
+        let mut roots = vec![concat!(env!("CARGO_MANIFEST_DIR"), "/embed/site")];
+        if cfg!(feature = "embed") {
+            roots.push("embed://site");
+        }
+        roots
+    }
+
+    /// Send raw HTTP/1.1 `request` to `addr` and read the whole response
+    ///
+    /// A response cut off by the server keeps the bytes read before the connection closed
+    pub(crate) async fn send_raw(addr: SocketAddr, request: &str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut res = vec![];
+        let _ = stream.read_to_end(&mut res).await;
+        String::from_utf8_lossy(&res).into_owned()
+    }
+
+    /// Leak host listen on `addr` with single route at `/`
+    fn leak_host(addr: SocketAddr, options: &str, route: &str) -> &'static SettingHost {
+        let mut settings: crate::config::Settings = toml::from_str(&format!(
+            r#"
+[[host]]
+ip = "{}"
+port = {}
+shutdown_timeout = "5s"
+{options}
+route = []
+"#,
+            addr.ip(),
+            addr.port()
+        ))
+        .unwrap();
+        let mut host = settings.host.remove(0);
+        let route: crate::config::SettingRoute =
+            toml::from_str(&format!("location = \"/\"\n{route}")).unwrap();
+        host.route_map.insert("/".into(), route);
+        Box::leak(Box::new(host))
+    }
+
+    #[tokio::test]
+    async fn mk_server_adopts_listener() {
+        let prebound = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = prebound.local_addr().unwrap();
+        let host = leak_host(addr, "", "return = { status = 200, body = \"ok\" }");
+
+        let mut inherited = vec![prebound];
+        let listener = crate::utils::listenfd::take_listener(&host.addr(), &mut inherited).unwrap();
+        let shutdown = Shutdown::default();
+        let server = tokio::spawn(host.mk_server(listener, shutdown.clone()));
+
+        let res = send_raw(
+            addr,
+            "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert!(res.starts_with("HTTP/1.1 200 OK"));
+        assert!(res.ends_with("ok"));
+
+        shutdown.terminate.cancel();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn mk_server_drains_in_flight_requests() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // upstream answers after shutdown started
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nslow")
+                .await
+                .unwrap();
+        });
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let host = leak_host(
+            addr,
+            "",
+            &format!("proxy_pass = \"http://{upstream_addr}\""),
+        );
+        let shutdown = Shutdown::default();
+        let server = tokio::spawn(host.mk_server(listener, shutdown.clone()));
+
+        let res = tokio::spawn(send_raw(
+            addr,
+            "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        ));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        shutdown.terminate.cancel();
+
+        let res = res.await.unwrap();
+        assert!(res.starts_with("HTTP/1.1 200 OK"));
+        assert!(res.ends_with("slow"));
+        server.await.unwrap().unwrap();
+        // listener closed after shutdown
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn mk_server_return_route() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let host = leak_host(
+            addr,
+            "headers = { X-Powered-By = \"candy\" }",
+            "return = { status = 200, body = \"ok\", content_type = \"text/plain\" }",
+        );
+        let shutdown = Shutdown::default();
+        let server = tokio::spawn(host.mk_server(listener, shutdown.clone()));
+
+        let res = send_raw(
+            addr,
+            "GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await
+        .to_lowercase();
+        assert!(res.starts_with("http/1.1 200 ok"));
+        assert!(res.contains("content-type: text/plain\r\n"));
+        assert!(res.contains("content-length: 2\r\n"));
+        // host headers from add_headers
+        assert!(res.contains("server: candy\r\n"));
+        assert!(res.contains("x-powered-by: candy\r\n"));
+        assert!(!res.contains("etag"));
+        assert!(!res.contains("last-modified"));
+        assert!(res.ends_with("\r\n\r\nok"));
+
+        shutdown.terminate.cancel();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn mk_server_filter_deny() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut host = leak_host(
+            addr,
+            "error_format = \"json\"\nrequest_filters = [{ match = \"uri\", contains = \".php\", action = \"deny\", status = 403 }]",
+            "return = { status = 200, body = \"ok\" }",
+        )
+        .clone();
+        let filter = &host.request_filters.as_ref().unwrap()[0];
+        host.filters = vec![crate::http::filter::RequestFilter::new(0, filter).unwrap()];
+        let host: &'static SettingHost = Box::leak(Box::new(host));
+        let shutdown = Shutdown::default();
+        let server = tokio::spawn(host.mk_server(listener, shutdown.clone()));
+
+        let res = send_raw(
+            addr,
+            "GET /index.php HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert!(res.starts_with("HTTP/1.1 403 Forbidden"));
+        assert!(res.to_lowercase().contains("server: candy\r\n"));
+        assert!(res.ends_with(r#"{"error":"request_rejected","message":"Forbidden"}"#));
+
+        shutdown.terminate.cancel();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn mk_server_proxy_timeouts() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // upstream sends headers and half of the body then stalls,
+        // or stalls before headers for paths starting with /slow
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = upstream.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buf = [0; 1024];
+                    let n = stream.read(&mut buf).await.unwrap();
+                    if buf[..n].starts_with(b"GET /slow") {
+                        tokio::time::sleep(Duration::from_secs(2)).await;
+                    }
+                    stream
+                        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello")
+                        .await
+                        .unwrap();
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                });
+            }
+        });
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let host = leak_host(
+            addr,
+            "",
+            &format!("proxy_pass = \"http://{upstream_addr}\"\nproxy_read_timeout_ms = 200"),
+        );
+        let shutdown = Shutdown::default();
+        let server = tokio::spawn(host.mk_server(listener, shutdown.clone()));
+
+        let get = |addr, path| async move {
+            let req =
+                format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+            send_raw(addr, &req).await
+        };
+
+        // stalled body is cut off by the read timeout
+        let start = std::time::Instant::now();
+        let res = tokio::time::timeout(Duration::from_secs(1), get(addr, "/"))
+            .await
+            .unwrap();
+        assert!(res.starts_with("HTTP/1.1 200 OK"));
+        assert!(res.ends_with("hello"));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // stalled headers get 504
+        let res = get(addr, "/slow").await;
+        assert!(res.starts_with("HTTP/1.1 504 Gateway Timeout"));
+
+        shutdown.terminate.cancel();
+        server.await.unwrap().unwrap();
+
+        // proxy_timeout bounds the whole request until headers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let host = leak_host(
+            addr,
+            "",
+            &format!("proxy_pass = \"http://{upstream_addr}\"\nproxy_timeout = \"200ms\""),
+        );
+        let shutdown = Shutdown::default();
+        let server = tokio::spawn(host.mk_server(listener, shutdown.clone()));
+        let start = std::time::Instant::now();
+        let res = get(addr, "/slow").await;
+        assert!(res.starts_with("HTTP/1.1 504 Gateway Timeout"));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        shutdown.terminate.cancel();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn mk_server_etag_per_encoding() {
+        init_settings();
+        for root in static_roots() {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.set_nonblocking(true).unwrap();
+            let addr = listener.local_addr().unwrap();
+            let host = leak_host(addr, "", &format!("root = \"{root}\""));
+            let shutdown = Shutdown::default();
+            let server = tokio::spawn(host.mk_server(listener, shutdown.clone()));
+
+            let get = |headers: String| async move {
+                let req = format!(
+                    "GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{headers}\r\n"
+                );
+                let res = send_raw(addr, &req).await.to_lowercase();
+                let header = |name: &str| {
+                    res.lines()
+                        .find_map(|line| line.strip_prefix(&format!("{name}: ")))
+                        .map(|value| value.trim().to_string())
+                };
+                (
+                    res[9..12].to_string(),
+                    header("etag").unwrap(),
+                    header("vary"),
+                )
+            };
+
+            let (status, identity, vary) = get(String::new()).await;
+            assert_eq!(status, "200");
+            assert_eq!(vary.as_deref(), Some("accept-encoding"));
+            let (status, gzip, vary) = get("Accept-Encoding: gzip\r\n".into()).await;
+            assert_eq!(status, "200");
+            assert_eq!(vary.as_deref(), Some("accept-encoding"));
+            assert_ne!(identity, gzip);
+            assert!(gzip.trim_end_matches('"').ends_with("-gzip"));
+
+            // each ETag only validates its own representation
+            let inm = |etag: &str, accept: &str| format!("If-None-Match: {etag}\r\n{accept}");
+            let (status, ..) = get(inm(&identity, "")).await;
+            assert_eq!(status, "304");
+            let (status, ..) = get(inm(&gzip, "Accept-Encoding: gzip\r\n")).await;
+            assert_eq!(status, "304");
+            let (status, ..) = get(inm(&identity, "Accept-Encoding: gzip\r\n")).await;
+            assert_eq!(status, "200");
+            let (status, ..) = get(inm(&gzip, "")).await;
+            assert_eq!(status, "200");
+
+            shutdown.terminate.cancel();
+            server.await.unwrap().unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn mk_server_static_methods() {
+        init_settings();
+        for root in static_roots() {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.set_nonblocking(true).unwrap();
+            let addr = listener.local_addr().unwrap();
+            let host = leak_host(addr, "", &format!("root = \"{root}\""));
+            let shutdown = Shutdown::default();
+            let server = tokio::spawn(host.mk_server(listener, shutdown.clone()));
+
+            let send = |method: &'static str| async move {
+                let req = format!(
+                    "{method} /index.html HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                );
+                send_raw(addr, &req).await.to_lowercase()
+            };
+
+            let res = send("GET").await;
+            assert!(res.starts_with("http/1.1 200"));
+            assert!(res.contains("<html"));
+            let res = send("HEAD").await;
+            assert!(res.starts_with("http/1.1 200"));
+            assert!(!res.contains("<html"));
+            let res = send("OPTIONS").await;
+            assert!(res.starts_with("http/1.1 204"));
+            assert!(res.contains("allow: get, head, options\r\n"));
+            for method in ["POST", "PUT", "DELETE"] {
+                let res = send(method).await;
+                assert!(res.starts_with("http/1.1 405"));
+                assert!(res.contains("allow: get, head, options\r\n"));
+                // rendered by error_response like other errors
+                assert!(res.ends_with("method not allowed"));
+            }
+
+            shutdown.terminate.cancel();
+            server.await.unwrap().unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn mk_server_health_draining() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let host = leak_host(
+            addr,
+            "health_endpoint = \"/healthz\"\nshutdown_delay = \"500ms\"",
+            "return = { status = 200, body = \"ok\" }",
+        );
+        let shutdown = Shutdown::default();
+        let server = tokio::spawn(host.mk_server(listener, shutdown.clone()));
+
+        let probe = || {
+            send_raw(
+                addr,
+                "GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+        };
+
+        let res = probe().await;
+        assert!(res.starts_with("HTTP/1.1 200 OK"));
+        assert!(res.contains(r#"{"status":"ok""#));
+
+        // still accepting while draining
+        shutdown.terminate.cancel();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let res = probe().await;
+        assert!(res.starts_with("HTTP/1.1 503 Service Unavailable"));
+        assert!(res.contains(r#"{"status":"draining""#));
+
+        server.await.unwrap().unwrap();
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn mk_server_handover_skips_draining() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let host = leak_host(
+            addr,
+            "shutdown_delay = \"10s\"",
+            "return = { status = 200, body = \"ok\" }",
+        );
+        let shutdown = Shutdown::default();
+        let server = tokio::spawn(host.mk_server(listener, shutdown.clone()));
+
+        // new process serves already, no delay and stays healthy
+        shutdown.handover.cancel();
+        tokio::time::timeout(Duration::from_secs(1), server)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(!host.draining.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn mk_server_compression_types() {
+        init_settings();
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        std::fs::write(root.join("logo.png"), [0x89, b'P', b'N', b'G'].repeat(256)).unwrap();
+        std::fs::write(root.join("data.json"), r#"{"items":[1,2,3]}"#.repeat(64)).unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let host = leak_host(addr, "", &format!("root = \"{}\"", root.display()));
+        let shutdown = Shutdown::default();
+        let server = tokio::spawn(host.mk_server(listener, shutdown.clone()));
+
+        let get = |path: &'static str, accept: &'static str| async move {
+            let req = format!(
+                "GET {path} HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: {accept}\r\nConnection: close\r\n\r\n"
+            );
+            let res = send_raw(addr, &req).await.to_lowercase();
+            let head = res.split("\r\n\r\n").next().unwrap().to_string();
+            let header = |name: &str| {
+                head.lines()
+                    .find_map(|line| line.strip_prefix(&format!("{name}: ")))
+                    .map(str::to_string)
+            };
+            (
+                header("content-encoding"),
+                header("content-length"),
+                header("vary"),
+            )
+        };
+
+        // already compressed types pass through
+        let (encoding, _, vary) = get("/logo.png", "gzip, br, zstd").await;
+        assert_eq!(encoding, None);
+        assert_eq!(vary, None);
+
+        // client preferred algorithm, server preference breaks ties
+        let (encoding, length, vary) = get("/data.json", "gzip, zstd;q=0.5").await;
+        assert_eq!(encoding.as_deref(), Some("gzip"));
+        assert_eq!(length, None);
+        assert_eq!(vary.as_deref(), Some("accept-encoding"));
+        let (encoding, ..) = get("/data.json", "gzip, zstd").await;
+        assert_eq!(encoding.as_deref(), Some("zstd"));
+        let (encoding, ..) = get("/data.json", "gzip, br, zstd").await;
+        assert_eq!(encoding.as_deref(), Some("br"));
+
+        shutdown.terminate.cancel();
+        server.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn host_error_response_works() {
+        let addr = "127.0.0.1:4000".parse().unwrap();
+        let host = leak_host(
+            addr,
+            "headers = { X-Powered-By = \"candy\" }",
+            "return = { status = 200 }",
+        );
+        let err = Error::Rejected(StatusCode::TOO_MANY_REQUESTS);
+        let res = host_error_response(host, &err, ErrorFormat::Json);
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["Server"], "candy");
+        assert_eq!(res.headers()["X-Powered-By"], "candy");
+        let body = futures_util::FutureExt::now_or_never(http_body_util::BodyExt::collect(
+            res.into_body(),
+        ))
+        .unwrap()
+        .unwrap()
+        .to_bytes();
+        assert_eq!(
+            body,
+            r#"{"error":"request_rejected","message":"Too Many Requests"}"#
+        );
+    }
+
+    #[test]
+    fn format_latency_works() {
+        assert_eq!(format_latency(Duration::from_nanos(999)), "999ns");
+        assert_eq!(format_latency(Duration::from_nanos(1_000)), "1μs");
+        assert_eq!(format_latency(Duration::from_nanos(999_999)), "999μs");
+        assert_eq!(format_latency(Duration::from_nanos(1_000_000)), "1ms");
+    }
+}
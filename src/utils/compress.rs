@@ -2,18 +2,131 @@ use async_compression::tokio::bufread::{BrotliEncoder, DeflateEncoder, GzipEncod
 use futures_util::TryStreamExt;
 use http_body_util::{BodyExt, StreamBody};
 use hyper::body::{Bytes, Frame};
+use serde::Deserialize;
 use tokio::io::{AsyncBufRead, BufReader};
 use tokio_util::io::ReaderStream;
 
-use crate::{error::Error, http::CandyBody};
+use crate::{error::Error, http::CandyBody, utils::negotiate::parse_quality_values};
 
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
 pub enum CompressType {
     Zstd,
     Gzip,
     Deflate,
+    #[serde(rename = "br")]
     Brotli,
 }
 
+impl CompressType {
+    /// Choose encoding from client `Accept-Encoding` header,
+    /// the highest quality wins and `prefer` order breaks ties
+    ///
+    /// ## Arguments
+    ///
+    /// `accept`: `Accept-Encoding` header value, like `gzip, br;q=0.8`
+    /// `prefer`: encodings enabled on server by preference
+    pub fn from_accept(accept: &str, prefer: &[CompressType]) -> Option<Self> {
+        let accepted = parse_quality_values(accept);
+        let quality = |name: &str| {
+            accepted
+                .iter()
+                .find(|(coding, _)| coding.eq_ignore_ascii_case(name))
+                .or_else(|| accepted.iter().find(|(coding, _)| *coding == "*"))
+                .map(|(_, quality)| *quality)
+        };
+
+        let mut chosen: Option<(Self, f32)> = None;
+        for encoding in prefer {
+            let Some(q) = quality(encoding.name()).filter(|q| *q > 0.0) else {
+                continue;
+            };
+            if !chosen.is_some_and(|(_, best)| q <= best) {
+                chosen = Some((*encoding, q));
+            }
+        }
+        chosen.map(|(encoding, _)| encoding)
+    }
+
+    /// Value of `Content-Encoding` header
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompressType::Zstd => "zstd",
+            CompressType::Gzip => "gzip",
+            CompressType::Deflate => "deflate",
+            CompressType::Brotli => "br",
+        }
+    }
+}
+
+/// Check response of content type can be compressed
+///
+/// ## Arguments
+///
+/// `content_type`: response `Content-Type`, parameters are ignored
+/// `exclude_types`: types never compressed, entries end with `/` match the whole group
+pub fn is_compressible(content_type: &str, exclude_types: &[String]) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    !exclude_types.iter().any(|exclude| {
+        let exclude = exclude.to_ascii_lowercase();
+        if exclude.ends_with('/') {
+            mime.starts_with(&exclude)
+        } else {
+            mime == exclude
+        }
+    })
+}
+
+/// ETag of compressed representation, the encoding is appended like nginx
+/// so caches never validate a compressed body with the identity ETag
+///
+/// ## Arguments
+///
+/// `etag`: ETag of uncompressed content, quoted or not
+/// `encoding`: compress type of response body
+pub fn encoded_etag(etag: &str, encoding: CompressType) -> String {
+    match etag.strip_suffix('"') {
+        Some(etag) => format!("{etag}-{}\"", encoding.name()),
+        None => format!("{etag}-{}", encoding.name()),
+    }
+}
+
+/// Remove encoding suffix added by [`encoded_etag`] from every entity tag of
+/// `If-None-Match` or `If-Match`, so upstream validates its own ETag
+///
+/// ## Arguments
+///
+/// `value`: conditional header value, like `"abc-gzip", W/"def-br"` or `*`
+pub fn strip_encoded_etags(value: &str) -> String {
+    const ENCODINGS: [CompressType; 4] = [
+        CompressType::Zstd,
+        CompressType::Gzip,
+        CompressType::Deflate,
+        CompressType::Brotli,
+    ];
+    value
+        .split(',')
+        .map(|etag| {
+            let etag = etag.trim();
+            let (tag, quote) = match etag.strip_suffix('"') {
+                Some(tag) => (tag, "\""),
+                None => (etag, ""),
+            };
+            let tag = ENCODINGS
+                .iter()
+                .find_map(|encoding| tag.strip_suffix(&format!("-{}", encoding.name())))
+                .unwrap_or(tag);
+            format!("{tag}{quote}")
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 macro_rules! encode {
     ($encoder:ident, $file:ident) => {{
         let encoder_stream = $encoder::new($file);
@@ -41,3 +154,70 @@ where
         Brotli => encode!(BrotliEncoder, file_reader),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_accept_works() {
+        use CompressType::*;
+
+        let prefer = [Brotli, Zstd, Gzip, Deflate];
+        assert_eq!(
+            CompressType::from_accept("gzip, deflate, br", &prefer),
+            Some(Brotli)
+        );
+        assert_eq!(CompressType::from_accept("gzip, zstd", &prefer), Some(Zstd));
+        assert_eq!(
+            CompressType::from_accept("br;q=0.5, gzip;q=0.9", &prefer),
+            Some(Gzip)
+        );
+        assert_eq!(
+            CompressType::from_accept("br;q=0, gzip", &prefer),
+            Some(Gzip)
+        );
+        assert_eq!(CompressType::from_accept("*", &prefer), Some(Brotli));
+        assert_eq!(CompressType::from_accept("br, *;q=0", &[Gzip]), None);
+        assert_eq!(CompressType::from_accept("identity", &prefer), None);
+        assert_eq!(
+            CompressType::from_accept("gzip, br", &[Gzip, Brotli]),
+            Some(Gzip)
+        );
+    }
+
+    #[test]
+    fn is_compressible_works() {
+        let exclude = ["image/".to_string(), "text/event-stream".to_string()];
+        assert!(is_compressible("application/json", &exclude));
+        assert!(is_compressible("text/html; charset=utf-8", &exclude));
+        assert!(!is_compressible("image/png", &exclude));
+        assert!(!is_compressible(
+            "Text/Event-Stream; charset=utf-8",
+            &exclude
+        ));
+    }
+
+    #[test]
+    fn encoded_etag_works() {
+        assert_eq!(encoded_etag("\"abc\"", CompressType::Gzip), "\"abc-gzip\"");
+        assert_eq!(
+            encoded_etag("W/\"abc\"", CompressType::Brotli),
+            "W/\"abc-br\""
+        );
+        assert_eq!(
+            encoded_etag("1700000000-42", CompressType::Zstd),
+            "1700000000-42-zstd"
+        );
+    }
+
+    #[test]
+    fn strip_encoded_etags_works() {
+        assert_eq!(strip_encoded_etags("\"abc-gzip\""), "\"abc\"");
+        assert_eq!(
+            strip_encoded_etags("W/\"abc-br\",\"def\", \"ghi-zstd\""),
+            "W/\"abc\", \"def\", \"ghi\""
+        );
+        assert_eq!(strip_encoded_etags("*"), "*");
+    }
+}
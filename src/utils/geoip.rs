@@ -0,0 +1,63 @@
+use std::net::IpAddr;
+
+use anyhow::anyhow;
+
+use crate::{config::SettingGeoip, error::Result};
+
+#[cfg(feature = "geoip")]
+static GEOIP: std::sync::OnceLock<maxminddb::Reader<maxminddb::Mmap>> = std::sync::OnceLock::new();
+
+/// Open MaxMind database from config, the file is mmap-backed
+///
+/// ## Arguments
+///
+/// `geoip`: geoip setting from config
+#[cfg(feature = "geoip")]
+pub fn init_geoip(geoip: &SettingGeoip) -> Result<()> {
+    let reader = maxminddb::Reader::open_mmap(&geoip.database)
+        .map_err(|err| anyhow!("open geoip database {} failed {err}", geoip.database))?;
+    GEOIP
+        .set(reader)
+        .map_err(|_| anyhow!("geoip database already opened"))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "geoip"))]
+pub fn init_geoip(geoip: &SettingGeoip) -> Result<()> {
+    Err(anyhow!(
+        "geoip database {} requires candy built with the `geoip` feature",
+        geoip.database
+    )
+    .into())
+}
+
+/// ISO country code of client ip address
+///
+/// ## Return
+///
+/// empty when database not opened or the address not found
+#[cfg(feature = "geoip")]
+pub fn country_code(ip: IpAddr) -> &'static str {
+    GEOIP
+        .get()
+        .and_then(|reader| reader.lookup::<maxminddb::geoip2::Country>(ip).ok())
+        .and_then(|country| country.country)
+        .and_then(|country| country.iso_code)
+        .unwrap_or_default()
+}
+
+#[cfg(not(feature = "geoip"))]
+pub fn country_code(_ip: IpAddr) -> &'static str {
+    ""
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn country_code_without_database() {
+        assert_eq!(country_code("1.1.1.1".parse().unwrap()), "");
+        assert_eq!(country_code("::1".parse().unwrap()), "");
+    }
+}
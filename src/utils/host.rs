@@ -0,0 +1,182 @@
+use std::net::Ipv6Addr;
+
+use http::Request;
+
+use crate::error::{Error, Result};
+
+/// Parse `Host` header value to host name and port
+/// https://datatracker.ietf.org/doc/html/rfc7230#section-5.4
+///
+/// ## Arguments
+///
+/// `host`: host header value or request target authority
+///
+/// ## Return
+///
+/// host name and port, IPv6 literal keeps the brackets like `[::1]`.
+/// none when the host contains userinfo, invalid characters or port
+pub fn parse_host(host: &str) -> Option<(&str, Option<u16>)> {
+    let (name, port) = if host.starts_with('[') {
+        let end = host.find(']')?;
+        let (name, rest) = host.split_at(end + 1);
+        name[1..end].parse::<Ipv6Addr>().ok()?;
+        match rest {
+            "" => (name, None),
+            rest => (name, Some(rest.strip_prefix(':')?)),
+        }
+    } else {
+        match host.rsplit_once(':') {
+            Some((name, port)) => (name, Some(port)),
+            None => (host, None),
+        }
+    };
+    let valid_name = !name.is_empty()
+        && (name.starts_with('[')
+            || name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_')));
+    if !valid_name {
+        return None;
+    }
+    let port = match port {
+        Some(port) if !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()) => {
+            Some(port.parse().ok()?)
+        }
+        Some(_) => return None,
+        None => None,
+    };
+    Some((name, port))
+}
+
+/// Validate request host from request target and `Host` header
+///
+/// Absolute-form request target takes precedence, but its authority must match
+/// the `Host` header when both exist, avoid routing ambiguous requests.
+/// Default port of the target scheme is ignored when comparing,
+/// so `http://example.com:80/` matches `Host: example.com`.
+///
+/// ## Arguments
+///
+/// `req`: client request
+///
+/// ## Return
+///
+/// host name and port, none when neither exists like HTTP/1.0 request without `Host`
+pub fn request_host<'a, T>(req: &'a Request<T>) -> Result<Option<(&'a str, Option<u16>)>> {
+    let bad_request = |reason: &str| Error::BadRequest(format!("{reason} {}", req.uri()).into());
+    let mut headers = req.headers().get_all("host").iter();
+    let header = match (headers.next(), headers.next()) {
+        (Some(_), Some(_)) => return Err(bad_request("multiple host headers")),
+        (Some(host), None) => Some(host.to_str().map_err(|_| bad_request("invalid host"))?),
+        _ => None,
+    };
+    let parse = |host: &'a str| parse_host(host).ok_or_else(|| bad_request("invalid host"));
+    let authority = req
+        .uri()
+        .authority()
+        .map(|authority| parse(authority.as_str()))
+        .transpose()?;
+    let header = header.map(parse).transpose()?;
+    let default_port = match req.uri().scheme_str() {
+        Some("http") => Some(80),
+        Some("https") => Some(443),
+        _ => None,
+    };
+    let port = |port: Option<u16>| port.filter(|port| Some(*port) != default_port);
+    match (authority, header) {
+        (Some(authority), Some(header)) => {
+            if !authority.0.eq_ignore_ascii_case(header.0) || port(authority.1) != port(header.1) {
+                return Err(bad_request("host header mismatch request target"));
+            }
+            Ok(Some(authority))
+        }
+        (Some(host), None) | (None, Some(host)) => Ok(Some(host)),
+        (None, None) if req.version() == http::Version::HTTP_11 => {
+            Err(bad_request("missing host header"))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_host_works() {
+        assert_eq!(parse_host("example.com"), Some(("example.com", None)));
+        assert_eq!(
+            parse_host("example.com:8080"),
+            Some(("example.com", Some(8080)))
+        );
+        assert_eq!(parse_host("127.0.0.1:80"), Some(("127.0.0.1", Some(80))));
+        assert_eq!(parse_host("[::1]"), Some(("[::1]", None)));
+        assert_eq!(parse_host("[::1]:8080"), Some(("[::1]", Some(8080))));
+        assert_eq!(parse_host("my_host.local"), Some(("my_host.local", None)));
+        assert_eq!(parse_host(""), None);
+        assert_eq!(parse_host(":8080"), None);
+        assert_eq!(parse_host("example.com:"), None);
+        assert_eq!(parse_host("example.com:99999"), None);
+        assert_eq!(parse_host("example.com:80:80"), None);
+        assert_eq!(parse_host("user@example.com"), None);
+        assert_eq!(parse_host("example.com/path"), None);
+        assert_eq!(parse_host("[::1"), None);
+        assert_eq!(parse_host("[::1]8080"), None);
+        assert_eq!(parse_host("[example.com]"), None);
+        assert_eq!(parse_host("::1"), None);
+    }
+
+    #[test]
+    fn request_host_works() {
+        let req = Request::builder()
+            .uri("/")
+            .header("host", "[::1]:4000")
+            .body(())
+            .unwrap();
+        assert_eq!(request_host(&req).unwrap(), Some(("[::1]", Some(4000))));
+
+        let req = Request::builder()
+            .uri("http://example.com/")
+            .header("host", "other.com")
+            .body(())
+            .unwrap();
+        assert!(request_host(&req).is_err());
+
+        // default port of scheme is ignored
+        for (uri, host) in [
+            ("http://example.com:80/", "example.com"),
+            ("http://Example.com/", "example.com:80"),
+            ("https://example.com:443/", "example.com"),
+        ] {
+            let req = Request::builder()
+                .uri(uri)
+                .header("host", host)
+                .body(())
+                .unwrap();
+            assert!(request_host(&req).is_ok(), "{uri} {host}");
+        }
+        let req = Request::builder()
+            .uri("https://example.com:80/")
+            .header("host", "example.com")
+            .body(())
+            .unwrap();
+        assert!(request_host(&req).is_err());
+
+        let req = Request::builder()
+            .uri("http://example.com/")
+            .body(())
+            .unwrap();
+        assert_eq!(request_host(&req).unwrap(), Some(("example.com", None)));
+
+        let req = Request::builder().uri("/").body(()).unwrap();
+        assert!(request_host(&req).is_err());
+
+        let req = Request::builder()
+            .uri("/")
+            .header("host", "a.com")
+            .header("host", "b.com")
+            .body(())
+            .unwrap();
+        assert!(request_host(&req).is_err());
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::IpAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use tokio::time::{sleep_until, Instant, Sleep};
+
+use crate::{error::Error, http::CandyBody};
+
+/// Response body released at limited rate
+/// Frames after the unthrottled window wait until the paced deadline
+pub struct LimitRate {
+    body: CandyBody<Bytes>,
+    /// Bytes per second
+    rate: u64,
+    /// Bytes sent without limit
+    after: u64,
+    /// Bytes sent
+    sent: u64,
+    /// Time when the first throttled frame arrived
+    start: Option<Instant>,
+    /// Frame waiting for its deadline
+    pending: Option<(Pin<Box<Sleep>>, Frame<Bytes>)>,
+}
+
+impl Body for LimitRate {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = &mut *self;
+        if this.pending.is_none() {
+            let frame = match ready!(Pin::new(&mut this.body).poll_frame(cx)) {
+                Some(Ok(frame)) => frame,
+                other => return Poll::Ready(other),
+            };
+            let Some(data) = frame.data_ref() else {
+                return Poll::Ready(Some(Ok(frame)));
+            };
+            this.sent += data.len() as u64;
+            let throttled = this.sent.saturating_sub(this.after);
+            if throttled == 0 {
+                return Poll::Ready(Some(Ok(frame)));
+            }
+            let start = *this.start.get_or_insert_with(Instant::now);
+            let deadline = start + Duration::from_secs_f64(throttled as f64 / this.rate as f64);
+            this.pending = Some((Box::pin(sleep_until(deadline)), frame));
+        }
+        if let Some((sleep, _)) = &mut this.pending {
+            ready!(sleep.as_mut().poll(cx));
+        }
+        Poll::Ready(this.pending.take().map(|(_, frame)| Ok(frame)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.pending.is_none() && self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let pending = self
+            .pending
+            .as_ref()
+            .and_then(|(_, frame)| frame.data_ref())
+            .map_or(0, |data| data.len() as u64);
+        let inner = self.body.size_hint();
+        let mut hint = SizeHint::new();
+        hint.set_lower(inner.lower() + pending);
+        if let Some(upper) = inner.upper() {
+            hint.set_upper(upper + pending);
+        }
+        hint
+    }
+}
+
+/// Limit response body rate
+///
+/// ## Arguments
+///
+/// `body`: response body
+/// `rate`: bytes per second, `0` disables the limit
+/// `after`: bytes sent without limit
+pub fn limit_rate(body: CandyBody<Bytes>, rate: u64, after: u64) -> CandyBody<Bytes> {
+    if rate == 0 {
+        return body;
+    }
+    LimitRate {
+        body,
+        rate,
+        after,
+        sent: 0,
+        start: None,
+        pending: None,
+    }
+    .boxed()
+}
+
+/// In-flight requests counter of each client
+#[derive(Clone, Debug, Default)]
+pub struct ConnLimiter {
+    conns: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    /// Rejected requests count
+    rejected: Arc<AtomicU64>,
+}
+
+/// Hold one in-flight request of client, released when dropped
+#[derive(Debug)]
+pub struct ConnGuard {
+    limiter: ConnLimiter,
+    ip: IpAddr,
+}
+
+impl ConnLimiter {
+    /// Count a new request of client
+    ///
+    /// ## Arguments
+    ///
+    /// `ip`: client ip address
+    /// `max`: max in-flight requests of one client
+    ///
+    /// ## Return
+    ///
+    /// none when client already has max in-flight requests
+    pub fn acquire(&self, ip: IpAddr, max: usize) -> Option<ConnGuard> {
+        let mut conns = self.conns.lock().ok()?;
+        let count = conns.entry(ip).or_default();
+        if *count >= max {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        *count += 1;
+        Some(ConnGuard {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+
+    /// Rejected requests count since server started
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for ConnGuard {
+    fn drop(&mut self) {
+        let Ok(mut conns) = self.limiter.conns.lock() else {
+            return;
+        };
+        if let Some(count) = conns.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                conns.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Response body holds the in-flight request until the body is finished or dropped
+pub struct GuardBody {
+    body: CandyBody<Bytes>,
+    _guard: ConnGuard,
+}
+
+impl Body for GuardBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Pin::new(&mut self.body).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        self.body.size_hint()
+    }
+}
+
+/// Keep the in-flight request counted during the whole response body
+pub fn guard_body(body: CandyBody<Bytes>, guard: ConnGuard) -> CandyBody<Bytes> {
+    GuardBody {
+        body,
+        _guard: guard,
+    }
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::stream;
+    use http_body_util::{Full, StreamBody};
+
+    use super::*;
+
+    #[test]
+    fn conn_limiter_works() {
+        let limiter = ConnLimiter::default();
+        let (a, b) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let mut guards = (0..10)
+            .map(|_| limiter.acquire(a, 10).unwrap())
+            .collect::<Vec<_>>();
+        assert!(limiter.acquire(a, 10).is_none());
+        assert_eq!(limiter.rejected(), 1);
+        assert!(limiter.acquire(b, 10).is_some());
+
+        // released when response body dropped
+        let body = http_body_util::Full::new(Bytes::new())
+            .map_err(|e| match e {})
+            .boxed();
+        let body = guard_body(body, guards.pop().unwrap());
+        assert!(limiter.acquire(a, 10).is_none());
+        drop(body);
+        assert!(limiter.acquire(a, 10).is_some());
+    }
+
+    #[tokio::test]
+    async fn limit_rate_works() {
+        let chunk = Bytes::from(vec![b'a'; 64 * 1024]);
+        let frames = (0..32).map(move |_| Ok::<_, Error>(Frame::data(chunk.clone())));
+        let body = StreamBody::new(stream::iter(frames)).boxed();
+        let start = std::time::Instant::now();
+        let bytes = limit_rate(body, 1024 * 1024, 0)
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes();
+        assert_eq!(bytes.len(), 2 * 1024 * 1024);
+        assert!(start.elapsed() >= Duration::from_millis(1800));
+
+        // Content-Length of the inner body is kept
+        let body = Full::new(Bytes::from_static(b"hello"))
+            .map_err(|e| -> Error { match e {} })
+            .boxed();
+        let body = limit_rate(body, 1, 0);
+        assert_eq!(body.size_hint().exact(), Some(5));
+
+        // zero rate is unlimited
+        let chunk = Bytes::from(vec![b'a'; 1024 * 1024]);
+        let body = Full::new(chunk)
+            .map_err(|e| -> Error { match e {} })
+            .boxed();
+        let start = std::time::Instant::now();
+        let bytes = limit_rate(body, 0, 0).collect().await.unwrap().to_bytes();
+        assert_eq!(bytes.len(), 1024 * 1024);
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    env,
+    net::{SocketAddr, TcpListener},
+};
+
+use anyhow::Context;
+use tracing::{info, warn};
+
+/// First inherited file descriptor, same as systemd `SD_LISTEN_FDS_START`
+const LISTEN_FDS_START: i32 = 3;
+/// Parent pid env of binary upgrade, the new process checks it against `getppid`
+#[cfg(unix)]
+const UPGRADE_PID: &str = "CANDY_UPGRADE_PID";
+/// Readiness socket env of binary upgrade
+#[cfg(unix)]
+const UPGRADE_READY: &str = "CANDY_UPGRADE_READY";
+/// Temporary file descriptors are duplicated above it,
+/// out of the target range of inherited listeners
+#[cfg(unix)]
+const UPGRADE_FDS_TEMP: i32 = 1024;
+/// Max time to wait for the new process to bind listeners
+#[cfg(unix)]
+const UPGRADE_READY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Listeners inherited from parent process,
+/// passed by binary upgrade or systemd socket activation
+#[derive(Debug, Default)]
+pub struct Inherited {
+    pub listeners: Vec<TcpListener>,
+    /// Readiness socket of binary upgrade, parent process waits on it
+    #[cfg(unix)]
+    ready: Option<std::os::unix::net::UnixStream>,
+}
+
+/// Check whether inherited file descriptor is a socket
+#[cfg(unix)]
+fn is_socket(fd: i32) -> bool {
+    // SAFETY: fstat only writes the stat buffer
+    let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+    unsafe { libc::fstat(fd, &mut stat) == 0 && stat.st_mode & libc::S_IFMT == libc::S_IFSOCK }
+}
+
+/// Take listeners inherited from parent process by `LISTEN_FDS` env
+///
+/// The fds are only adopted when `LISTEN_PID` is current process set by systemd,
+/// or `CANDY_UPGRADE_PID` is parent process set by binary upgrade.
+/// The envs are removed after adopting, so child processes never inherit them.
+pub fn listen_fds() -> Inherited {
+    #[cfg(unix)]
+    {
+        use std::os::fd::FromRawFd;
+
+        let env_pid = |name| env::var(name).ok().and_then(|p| p.parse::<u32>().ok());
+        let systemd = env_pid("LISTEN_PID") == Some(std::process::id());
+        let upgrade = env_pid(UPGRADE_PID) == Some(std::os::unix::process::parent_id());
+        let count = env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|n| n.parse::<i32>().ok())
+            .filter(|_| systemd || upgrade)
+            .unwrap_or(0);
+        let ready = env::var(UPGRADE_READY)
+            .ok()
+            .and_then(|fd| fd.parse::<i32>().ok())
+            .filter(|fd| upgrade && *fd == LISTEN_FDS_START + count && is_socket(*fd))
+            // SAFETY: parent process passes readiness socket right after listeners
+            .map(|fd| unsafe { std::os::unix::net::UnixStream::from_raw_fd(fd) });
+        for name in ["LISTEN_FDS", "LISTEN_PID", UPGRADE_PID, UPGRADE_READY] {
+            env::remove_var(name);
+        }
+
+        let listeners = (LISTEN_FDS_START..LISTEN_FDS_START + count)
+            .filter(|fd| {
+                let socket = is_socket(*fd);
+                if !socket {
+                    warn!("inherited fd {fd} is not a socket, ignored");
+                }
+                socket
+            })
+            // SAFETY: parent process passes listening sockets start from fd 3
+            .map(|fd| unsafe { TcpListener::from_raw_fd(fd) })
+            .collect();
+        Inherited { listeners, ready }
+    }
+    #[cfg(not(unix))]
+    {
+        Inherited::default()
+    }
+}
+
+impl Inherited {
+    /// Tell parent process of binary upgrade all listeners are bound,
+    /// the parent starts draining after it
+    pub fn notify_ready(&mut self) {
+        #[cfg(unix)]
+        if let Some(mut ready) = self.ready.take() {
+            use std::io::Write;
+
+            if let Err(err) = ready.write_all(b"1") {
+                warn!("notify binary upgrade ready failed {err}");
+            }
+        }
+    }
+}
+
+/// Adopt inherited listener on the same address, or bind a new one
+///
+/// ## Arguments
+///
+/// `addr`: host address, like `0.0.0.0:4000`
+/// `inherited`: listeners inherited from parent process
+pub fn take_listener(addr: &str, inherited: &mut Vec<TcpListener>) -> anyhow::Result<TcpListener> {
+    let addrs = std::net::ToSocketAddrs::to_socket_addrs(addr)
+        .with_context(|| format!("invalid host address {addr}"))?
+        .collect::<Vec<SocketAddr>>();
+    let position = inherited.iter().position(|listener| {
+        listener
+            .local_addr()
+            .is_ok_and(|local| addrs.contains(&local))
+    });
+    let listener = match position {
+        Some(position) => {
+            info!("host adopt inherited listener on {}", addr);
+            inherited.swap_remove(position)
+        }
+        None => TcpListener::bind(&addrs[..]).with_context(|| format!("bind {addr} failed"))?,
+    };
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+/// New process spawned by binary upgrade, not serving until ready
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct Upgrade {
+    child: std::process::Child,
+    ready: std::os::unix::net::UnixStream,
+}
+
+/// Spawn new process of current binary with listeners,
+/// the new process adopts them by `LISTEN_FDS` env
+///
+/// ## Arguments
+///
+/// `listeners`: raw file descriptors of listening sockets
+#[cfg(unix)]
+pub fn spawn_upgrade(listeners: &[std::os::fd::RawFd]) -> anyhow::Result<Upgrade> {
+    use std::os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::{net::UnixStream, process::CommandExt},
+    };
+
+    let exe = env::current_exe().with_context(|| "get current binary failed")?;
+    let (ready, notify) = UnixStream::pair().with_context(|| "create readiness socket failed")?;
+    // duplicate above the target range in parent, close-on-exec keeps them
+    // out of other spawned processes
+    let dup = |fd: i32, min: i32| {
+        // SAFETY: fcntl returns a new owned fd or -1
+        match unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, min) } {
+            -1 => Err(std::io::Error::last_os_error()),
+            fd => Ok(unsafe { OwnedFd::from_raw_fd(fd) }),
+        }
+    };
+    let temp = listeners
+        .iter()
+        .copied()
+        .chain([notify.as_raw_fd()])
+        .map(|fd| dup(fd, UPGRADE_FDS_TEMP))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    drop(notify);
+    // occupy free target slots, so the exec error pipe of std
+    // never lands in the range overwritten after fork
+    let mut reserved = vec![];
+    for (i, fd) in temp.iter().enumerate() {
+        let target = LISTEN_FDS_START + i as i32;
+        // SAFETY: F_GETFD only checks whether fd is open
+        if unsafe { libc::fcntl(target, libc::F_GETFD) } == -1 {
+            let slot = dup(fd.as_raw_fd(), target)?;
+            if slot.as_raw_fd() != target {
+                return Err(anyhow::anyhow!("reserve fd {target} failed"));
+            }
+            reserved.push(slot);
+        }
+    }
+
+    let fds = temp.iter().map(|fd| fd.as_raw_fd()).collect::<Vec<_>>();
+    let mut command = std::process::Command::new(exe);
+    command
+        .args(env::args_os().skip(1))
+        .env("LISTEN_FDS", listeners.len().to_string())
+        .env_remove("LISTEN_PID")
+        .env(UPGRADE_PID, std::process::id().to_string())
+        .env(
+            UPGRADE_READY,
+            (LISTEN_FDS_START + listeners.len() as i32).to_string(),
+        );
+    // SAFETY: only async-signal-safe dup2 is called after fork
+    unsafe {
+        command.pre_exec(move || {
+            // dup2 clears FD_CLOEXEC, so the new process inherits them,
+            // temporary fds are closed by exec
+            for (i, fd) in fds.iter().enumerate() {
+                if libc::dup2(*fd, LISTEN_FDS_START + i as i32) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    let child = command
+        .spawn()
+        .with_context(|| "spawn new process failed")?;
+    Ok(Upgrade { child, ready })
+}
+
+#[cfg(unix)]
+impl Upgrade {
+    /// Wait until the new process bound all listeners,
+    /// it is killed when exits or times out before ready
+    ///
+    /// ## Return
+    ///
+    /// pid of the new process
+    pub async fn wait_ready(self) -> anyhow::Result<u32> {
+        use tokio::io::AsyncReadExt;
+
+        let Self { mut child, ready } = self;
+        let pid = child.id();
+        let wait = async {
+            ready.set_nonblocking(true)?;
+            let mut ready = tokio::net::UnixStream::from_std(ready)?;
+            let mut buf = [0; 1];
+            anyhow::Ok(ready.read(&mut buf).await? == 1)
+        };
+        let res = match tokio::time::timeout(UPGRADE_READY_TIMEOUT, wait).await {
+            Ok(Ok(true)) => return Ok(pid),
+            Ok(Ok(false)) => anyhow::anyhow!("new process {pid} exited before ready"),
+            Ok(Err(err)) => err.context("wait new process ready failed"),
+            Err(_) => anyhow::anyhow!("new process {pid} not ready in time"),
+        };
+        let _ = child.kill();
+        let _ = child.wait();
+        Err(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_listener_works() {
+        let prebound = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = prebound.local_addr().unwrap().to_string();
+        let mut inherited = vec![prebound];
+        let listener = take_listener(&addr, &mut inherited).unwrap();
+        assert_eq!(listener.local_addr().unwrap().to_string(), addr);
+        assert!(inherited.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn listen_fds_requires_pid() {
+        // fds of another process are never adopted
+        env::set_var("LISTEN_FDS", "1");
+        env::set_var("LISTEN_PID", (std::process::id() + 1).to_string());
+        let inherited = listen_fds();
+        assert!(inherited.listeners.is_empty());
+        assert!(inherited.ready.is_none());
+        assert!(env::var("LISTEN_FDS").is_err());
+        assert!(env::var("LISTEN_PID").is_err());
+
+        assert!(!is_socket(-1));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        assert!(is_socket(std::os::fd::AsRawFd::as_raw_fd(&listener)));
+    }
+}
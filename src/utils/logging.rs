@@ -1,16 +1,106 @@
+use std::fs::OpenOptions;
+
+use anyhow::{anyhow, Context};
+use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{
     fmt::{self},
     prelude::*,
-    registry, EnvFilter,
+    registry, EnvFilter, Layer, Registry,
 };
 
-pub fn init_logger() {
-    let formatting_layer = fmt::layer()
-        // .pretty()
-        // .with_thread_ids(true)
-        .with_target(false)
-        .with_writer(std::io::stdout);
+use crate::config::LogBackend;
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Init tracing logger with filter directives like `candy::http=debug,hyper=warn`
+///
+/// ## Arguments
+///
+/// `cli_level`: `--log-level` flag, overrides all
+/// `config_level`: `log_level` in config file, used when `CANDY_LOG` env is absent
+/// `backend`: `log_backend` in config file
+/// `log_file`: `log_file` in config file, used by file backend
+///
+/// ## Return
+///
+/// guard of the file writer thread, keep it until exit so buffered logs are flushed
+pub fn init_logger(
+    cli_level: Option<&str>,
+    config_level: Option<&str>,
+    backend: LogBackend,
+    log_file: Option<&str>,
+) -> anyhow::Result<Option<WorkerGuard>> {
+    let mut guard = None;
+    let output_layer: BoxedLayer = match backend {
+        LogBackend::Stdout => fmt::layer()
+            // .pretty()
+            // .with_thread_ids(true)
+            .with_target(false)
+            .with_writer(std::io::stdout)
+            .boxed(),
+        LogBackend::File => {
+            let file = open_log_file(log_file)?;
+            // writes on a dedicated thread, never block runtime threads on disk
+            let (writer, worker) = tracing_appender::non_blocking(file);
+            guard = Some(worker);
+            fmt::layer()
+                .with_target(false)
+                .with_ansi(false)
+                .with_writer(writer)
+                .boxed()
+        }
+        LogBackend::Journald => journald_layer()?,
+    };
+
+    let env_layer = match cli_level {
+        Some(level) => EnvFilter::new(level),
+        None => EnvFilter::try_from_env("CANDY_LOG")
+            .unwrap_or_else(|_| EnvFilter::new(config_level.unwrap_or("info"))),
+    };
+    registry().with(output_layer.with_filter(env_layer)).init();
+    Ok(guard)
+}
+
+/// Open `log_file` for appending
+fn open_log_file(log_file: Option<&str>) -> anyhow::Result<std::fs::File> {
+    let path = log_file.ok_or_else(|| anyhow!("log_backend file requires log_file"))?;
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("open log file {path} failed"))
+}
+
+/// systemd journal layer, tracing levels are mapped to syslog priorities
+#[cfg(feature = "journald")]
+fn journald_layer() -> anyhow::Result<BoxedLayer> {
+    let layer = tracing_journald::layer()
+        .context("connect to systemd journal failed")?
+        .with_syslog_identifier(crate::consts::NAME.to_string());
+    Ok(layer.boxed())
+}
+
+#[cfg(not(feature = "journald"))]
+fn journald_layer() -> anyhow::Result<BoxedLayer> {
+    Err(anyhow!(
+        "log_backend journald requires candy built with the `journald` feature"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_log_file_works() {
+        let err = open_log_file(None).unwrap_err();
+        assert_eq!(err.to_string(), "log_backend file requires log_file");
 
-    let env_layer = EnvFilter::try_from_env("CANDY_LOG").unwrap_or_else(|_| "info".into());
-    registry().with(env_layer).with(formatting_layer).init();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("candy.log");
+        std::fs::write(&path, "old\n").unwrap();
+        let mut file = open_log_file(path.to_str()).unwrap();
+        std::io::Write::write_all(&mut file, b"new\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old\nnew\n");
+    }
 }
@@ -1,6 +1,14 @@
 pub mod compress;
+pub mod geoip;
+pub mod host;
+pub mod limit;
+pub mod listenfd;
 pub mod logging;
+pub mod negotiate;
+pub mod normalize;
+pub mod referer;
 pub mod service;
+pub mod variables;
 
 pub use logging::*;
 pub use service::*;
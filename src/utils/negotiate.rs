@@ -0,0 +1,244 @@
+use std::path::Path;
+
+use http::HeaderMap;
+
+use crate::config::MIMEType;
+
+/// Parse header values with quality factor, like `Accept-Language: fr;q=0.8, en`
+/// https://datatracker.ietf.org/doc/html/rfc7231#section-5.3.1
+///
+/// ## Arguments
+///
+/// `header`: header value from client
+///
+/// ## Return
+///
+/// values and their quality factor in client order, quality defaults to `1.0`
+pub fn parse_quality_values(header: &str) -> Vec<(&str, f32)> {
+    header
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let value = params.next()?.trim();
+            if value.is_empty() {
+                return None;
+            }
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((value, quality))
+        })
+        .collect()
+}
+
+/// Parse header values with quality factor and sort them
+///
+/// ## Arguments
+///
+/// `header`: header value from client
+///
+/// ## Return
+///
+/// values sorted by quality factor from high to low, values with `q=0` are excluded
+pub fn parse_quality(header: &str) -> Vec<&str> {
+    let mut values = parse_quality_values(header)
+        .into_iter()
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect::<Vec<_>>();
+    // stable sort, keep client order for same quality
+    values.sort_by(|a, b| b.1.total_cmp(&a.1));
+    values.into_iter().map(|(value, _)| value).collect()
+}
+
+/// Choose index file of directory by `Accept` header
+///
+/// ## Arguments
+///
+/// `indexes`: existing index files by config order
+/// `headers`: client request headers
+/// `types`: MIME types from config, used to map file extension to media type
+///
+/// ## Return
+///
+/// the index file whose media type best matches `Accept`, or the first one
+pub fn negotiate_index(
+    mut indexes: Vec<String>,
+    headers: &HeaderMap,
+    types: &MIMEType,
+) -> Option<String> {
+    let accept = headers
+        .get("Accept")
+        .and_then(|value| value.to_str().ok())
+        .map(parse_quality)
+        .unwrap_or_default();
+    let media = |index: &String| {
+        let ext = Path::new(index).extension()?.to_str()?;
+        types.get(ext).map(|mime| mime.as_ref())
+    };
+    let matches = |accept: &str, media: &str| {
+        accept == "*/*"
+            || accept == media
+            || accept
+                .strip_suffix("/*")
+                .is_some_and(|kind| media.split('/').next() == Some(kind))
+    };
+    let position = accept.iter().find_map(|accept| {
+        indexes
+            .iter()
+            .position(|index| media(index).is_some_and(|media| matches(accept, media)))
+    });
+    match position {
+        Some(position) => Some(indexes.swap_remove(position)),
+        None => indexes.into_iter().next(),
+    }
+}
+
+/// Candidate variants of a static file by preference,
+/// `basename.{lang}.{ext}` by `Accept-Language`
+/// and `basename.{ext}` by `Accept` are tried before the file itself
+///
+/// ## Arguments
+///
+/// `path`: local file path resolved from request
+/// `headers`: client request headers
+/// `types`: MIME types from config, used to map `Accept` to file extension
+///
+/// ## Return
+///
+/// variant file paths preferred over the file itself, the caller serves the first existing one
+pub fn negotiate_variants(path: &str, headers: &HeaderMap, types: &MIMEType) -> Vec<String> {
+    let file = Path::new(path);
+    let (Some(stem), Some(ext), Some(parent)) = (
+        file.file_stem().and_then(|stem| stem.to_str()),
+        file.extension().and_then(|ext| ext.to_str()),
+        file.parent(),
+    ) else {
+        return vec![];
+    };
+
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(parse_quality)
+            .unwrap_or_default()
+    };
+    // en-US also matches en
+    let mut langs = vec![];
+    for lang in header("Accept-Language") {
+        if lang == "*" {
+            continue;
+        }
+        let lang = lang.to_ascii_lowercase();
+        let primary = lang.split('-').next().map(str::to_string);
+        langs.push(lang);
+        if let Some(primary) = primary.filter(|p| !langs.contains(p)) {
+            langs.push(primary);
+        }
+    }
+    let mut exts = header("Accept")
+        .into_iter()
+        .filter(|media| !media.ends_with("/*"))
+        .flat_map(|media| {
+            types
+                .iter()
+                .filter(move |(_, mime)| mime.as_ref() == media)
+                .map(|(ext, _)| ext.as_ref())
+        })
+        .collect::<Vec<_>>();
+    if !exts.contains(&ext) {
+        exts.push(ext);
+    }
+
+    exts.into_iter()
+        .flat_map(|ext| {
+            langs
+                .iter()
+                .map(move |lang| format!("{stem}.{lang}.{ext}"))
+                .chain(Some(format!("{stem}.{ext}")))
+        })
+        .filter_map(|name| parent.join(name).to_str().map(str::to_string))
+        .take_while(|variant| variant != path)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn parse_quality_works() {
+        assert_eq!(
+            parse_quality_values("gzip;q=0.5, br, ;q=1, *;q=0"),
+            vec![("gzip", 0.5), ("br", 1.0), ("*", 0.0)]
+        );
+        let langs = parse_quality("fr;q=0.8, en-US, de;q=0, zh;q=0.9");
+        assert_eq!(langs, vec!["en-US", "zh", "fr"]);
+        let accept = parse_quality("text/html, application/json;q=0.5");
+        assert_eq!(accept, vec!["text/html", "application/json"]);
+    }
+
+    #[test]
+    fn negotiate_variants_works() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        for name in ["index.html", "index.fr.html", "data.html", "data.json"] {
+            std::fs::write(dir.join(name), name).unwrap();
+        }
+        let types: MIMEType = BTreeMap::from([
+            ("html".into(), "text/html".into()),
+            ("json".into(), "application/json".into()),
+        ]);
+        let negotiate = |path: &str, headers: &HeaderMap| {
+            negotiate_variants(path, headers, &types)
+                .into_iter()
+                .find(|variant| Path::new(variant).is_file())
+        };
+        let index = dir.join("index.html");
+        let index = index.to_str().unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Accept-Language", "fr-CH, en;q=0.8".parse().unwrap());
+        let path = negotiate(index, &headers).unwrap();
+        assert!(path.ends_with("index.fr.html"));
+
+        headers.insert("Accept-Language", "de, en;q=0.8".parse().unwrap());
+        assert_eq!(negotiate(index, &headers), None);
+        // the file itself ends the candidates
+        let variants = negotiate_variants(index, &headers, &types);
+        assert!(variants.iter().all(|variant| variant != index));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Accept", "application/json".parse().unwrap());
+        let data = dir.join("data.html");
+        let path = negotiate(data.to_str().unwrap(), &headers).unwrap();
+        assert!(path.ends_with("data.json"));
+    }
+
+    #[test]
+    fn negotiate_index_works() {
+        let types: MIMEType = BTreeMap::from([
+            ("html".into(), "text/html".into()),
+            ("json".into(), "application/json".into()),
+        ]);
+        let indexes = || vec!["/www/index.html".to_string(), "/www/index.json".to_string()];
+        let choose = |accept: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("Accept", accept.parse().unwrap());
+            negotiate_index(indexes(), &headers, &types).unwrap()
+        };
+        assert_eq!(choose("application/json"), "/www/index.json");
+        assert_eq!(choose("text/html"), "/www/index.html");
+        assert_eq!(choose("text/html;q=0.5, application/*"), "/www/index.json");
+        assert_eq!(choose("*/*"), "/www/index.html");
+        assert_eq!(choose("image/png"), "/www/index.html");
+        assert_eq!(
+            negotiate_index(indexes(), &HeaderMap::new(), &types).unwrap(),
+            "/www/index.html"
+        );
+        assert_eq!(negotiate_index(vec![], &HeaderMap::new(), &types), None);
+    }
+}
@@ -0,0 +1,153 @@
+use std::borrow::Cow;
+
+use http::{uri::PathAndQuery, Request, Uri};
+
+use crate::error::{Error, Result};
+
+/// Request uri from client before normalization, used by `$request_uri`
+#[derive(Clone, Debug)]
+pub struct OriginalUri(pub Uri);
+
+/// Count dots of a dot segment, `%2E` is an encoded dot
+/// https://datatracker.ietf.org/doc/html/rfc3986#section-6.2.2.2
+fn dot_segment(segment: &str) -> Option<usize> {
+    match segment.to_ascii_lowercase().as_str() {
+        "." | "%2e" => Some(1),
+        ".." | ".%2e" | "%2e." | "%2e%2e" => Some(2),
+        _ => None,
+    }
+}
+
+/// Remove dot segments and optionally merge slashes of request path
+/// https://datatracker.ietf.org/doc/html/rfc3986#section-5.2.4
+///
+/// Percent-encoded slashes `%2F` are not decoded and stay in the segment.
+///
+/// ## Arguments
+///
+/// `path`: absolute request path starts with `/`
+/// `merge_slashes`: merge consecutive slashes into one
+///
+/// ## Return
+///
+/// borrowed path when it is already canonical
+pub fn normalize_path(path: &str, merge_slashes: bool) -> Cow<'_, str> {
+    let mut parts = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+    // keep leading empty segments when slashes are not merged
+    let leading = path.len() - path.trim_start_matches('/').len();
+    if !merge_slashes {
+        parts.splice(0..0, vec![""; leading.saturating_sub(1)]);
+    }
+    // the last segment decides trailing slash, `/a/` `/a/.` `/a/..`
+    let trailing = parts
+        .last()
+        .is_some_and(|last| last.is_empty() || dot_segment(last).is_some());
+    if parts.last().is_some_and(|last| last.is_empty()) {
+        parts.pop();
+    }
+
+    let mut segments = Vec::with_capacity(parts.len());
+    for part in parts {
+        match dot_segment(part) {
+            Some(1) => {}
+            Some(_) => {
+                segments.pop();
+            }
+            None if part.is_empty() && merge_slashes => {}
+            None => segments.push(part),
+        }
+    }
+    let mut normalized = format!("/{}", segments.join("/"));
+    if trailing && !segments.is_empty() {
+        normalized.push('/');
+    }
+    if normalized == path {
+        Cow::Borrowed(path)
+    } else {
+        Cow::Owned(normalized)
+    }
+}
+
+/// Replace request path with normalized path once before routing,
+/// the original uri is kept in request extensions as [`OriginalUri`]
+///
+/// ## Arguments
+///
+/// `req`: client request
+/// `merge_slashes`: host `merge_slashes` config
+pub fn normalize_request<T>(req: &mut Request<T>, merge_slashes: bool) -> Result<()> {
+    let path = req.uri().path();
+    // asterisk-form like `OPTIONS *`
+    if !path.starts_with('/') {
+        return Ok(());
+    }
+    let Cow::Owned(path) = normalize_path(path, merge_slashes) else {
+        return Ok(());
+    };
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path,
+    };
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse::<PathAndQuery>()?);
+    let uri = Uri::from_parts(parts)
+        .map_err(|err| Error::BadRequest(format!("invalid request uri {err}").into()))?;
+    let original = std::mem::replace(req.uri_mut(), uri);
+    req.extensions_mut().insert(OriginalUri(original));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::variables::variable_value;
+
+    #[test]
+    fn normalize_path_works() {
+        let merged = |path| normalize_path(path, true);
+        assert!(matches!(merged("/assets/app.js"), Cow::Borrowed(_)));
+        assert_eq!(merged("/"), "/");
+        assert_eq!(merged("//assets///app.js"), "/assets/app.js");
+        assert_eq!(merged("/a/./b"), "/a/b");
+        assert_eq!(merged("/a/b/../c"), "/a/c");
+        assert_eq!(merged("/a/b/.."), "/a/");
+        assert_eq!(merged("/a/."), "/a/");
+        assert_eq!(merged("/docs/"), "/docs/");
+        assert_eq!(merged("/../../etc/passwd"), "/etc/passwd");
+        assert_eq!(merged("/a/%2e%2E/b"), "/b");
+        assert_eq!(merged("/..."), "/...");
+        // encoded slash is a part of segment
+        assert_eq!(merged("/a%2F..%2Fb"), "/a%2F..%2Fb");
+        assert_eq!(merged("/a/%2F/../b"), "/a/b");
+
+        let kept = |path| normalize_path(path, false);
+        assert!(matches!(kept("//assets///app.js"), Cow::Borrowed(_)));
+        assert_eq!(kept("//a/./b/"), "//a/b/");
+        assert_eq!(kept("/a//../b"), "/a/b");
+    }
+
+    #[test]
+    fn normalize_request_works() {
+        let peer_addr = "127.0.0.1:8080".parse().unwrap();
+        let mut req = Request::get("//static/./js//app.js?v=1").body(()).unwrap();
+        normalize_request(&mut req, true).unwrap();
+        assert_eq!(req.uri().path(), "/static/js/app.js");
+        assert_eq!(req.uri().query(), Some("v=1"));
+        assert_eq!(
+            variable_value("uri", &req, &peer_addr).unwrap(),
+            "/static/js/app.js"
+        );
+        assert_eq!(
+            variable_value("request_uri", &req, &peer_addr).unwrap(),
+            "//static/./js//app.js?v=1"
+        );
+
+        let mut req = Request::get("/static/app.js").body(()).unwrap();
+        normalize_request(&mut req, true).unwrap();
+        assert!(req.extensions().get::<OriginalUri>().is_none());
+        assert_eq!(
+            variable_value("request_uri", &req, &peer_addr).unwrap(),
+            "/static/app.js"
+        );
+    }
+}
@@ -0,0 +1,92 @@
+/// Check `Referer` header by nginx style `valid_referers` list
+///
+/// ## Arguments
+///
+/// `referer`: client `Referer` header
+/// `valid_referers`: patterns from config, supports keywords
+///   `none`: request without `Referer`
+///   `blocked`: `Referer` without `http://` or `https://`, like deleted by a firewall
+///   `server_names`: `Referer` host is the request host
+///   and host patterns like `example.com`, `*.example.com`, `.example.com`, `www.example.*`
+///   or `example.com/galleries/` with a path prefix
+/// `host`: request host name without port
+pub fn valid_referer(referer: Option<&str>, valid_referers: &[String], host: Option<&str>) -> bool {
+    let has = |keyword: &str| valid_referers.iter().any(|valid| valid == keyword);
+    let Some(referer) = referer else {
+        return has("none");
+    };
+    let Some(url) = referer
+        .strip_prefix("http://")
+        .or_else(|| referer.strip_prefix("https://"))
+    else {
+        return has("blocked");
+    };
+    let (authority, path) = url.split_at(url.find('/').unwrap_or(url.len()));
+    let referer_host = authority
+        .rsplit_once(':')
+        .map_or(authority, |(name, _)| name)
+        .to_ascii_lowercase();
+    if has("server_names") && host.is_some_and(|host| host.eq_ignore_ascii_case(&referer_host)) {
+        return true;
+    }
+    valid_referers
+        .iter()
+        .filter(|valid| !matches!(valid.as_str(), "none" | "blocked" | "server_names"))
+        .any(|valid| {
+            let valid = valid.to_ascii_lowercase();
+            let (pattern, prefix) = valid.split_at(valid.find('/').unwrap_or(valid.len()));
+            match_host(pattern, &referer_host) && path.starts_with(prefix)
+        })
+}
+
+/// Match host by exact name or wildcard pattern
+fn match_host(pattern: &str, host: &str) -> bool {
+    if let Some(suffix) = pattern.strip_prefix('*') {
+        host.ends_with(suffix)
+    } else if let Some(domain) = pattern.strip_prefix('.') {
+        host == domain || host.ends_with(pattern)
+    } else if let Some(prefix) = pattern.strip_suffix('*') {
+        host.starts_with(prefix)
+    } else {
+        host == pattern
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_referer_works() {
+        let valid = |list: &[&str], referer: Option<&str>| {
+            let list = list.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+            valid_referer(referer, &list, Some("candy.dev"))
+        };
+        // none
+        assert!(valid(&["none"], None));
+        assert!(!valid(&["blocked"], None));
+        // blocked
+        assert!(valid(&["blocked"], Some("XXXXXXXX")));
+        assert!(!valid(&["none"], Some("XXXXXXXX")));
+        // server_names
+        assert!(valid(&["server_names"], Some("https://candy.dev:4000/a")));
+        assert!(!valid(&["server_names"], Some("https://evil.dev/")));
+        // wildcard
+        assert!(valid(
+            &["*.example.com"],
+            Some("http://img.Example.com/a.png")
+        ));
+        assert!(!valid(&["*.example.com"], Some("http://example.com/")));
+        assert!(valid(&[".example.com"], Some("http://example.com/")));
+        assert!(valid(&["www.example.*"], Some("http://www.example.org/")));
+        // exact with path prefix
+        assert!(valid(
+            &["example.com/galleries/"],
+            Some("http://example.com/galleries/1")
+        ));
+        assert!(!valid(
+            &["example.com/galleries/"],
+            Some("http://example.com/other")
+        ));
+    }
+}
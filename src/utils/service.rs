@@ -10,22 +10,53 @@ use crate::config::{HostRouteMap, SettingRoute};
 ///
 /// `assets_path`: the rest part of client request path
 /// `assets_root`: local directory path from config file
-/// `index_file`: index file format from config file
+#[inline]
+pub fn parse_file_path(assets_path: &str, assets_root: &str) -> String {
+    let root = assets_root.trim_end_matches('/');
+    let assets = assets_path.trim_start_matches('/');
+    format!("{}/{}", root, assets)
+}
+
+/// Parse index file path of assets directory
+///
+/// ## Arguments
+///
+/// `assets_path`: the rest part of client request path
+/// `assets_root`: local directory path from config file
+/// `index_file`: index file from config file, can be a relative path like `subdir/index.html`
 #[inline]
 pub fn parse_assets_path(assets_path: &str, assets_root: &str, index_file: &str) -> String {
-    match assets_path {
-        str if str.ends_with('/') => {
-            format!("{}{}{}", assets_root, assets_path, index_file)
-        }
-        str if str.contains('.') && !str.starts_with('/') => {
-            format!("{}/{}", assets_root, assets_path)
-        }
-        str if !str.starts_with('/') => {
-            format!("{}/{}/{}", assets_root, assets_path, index_file)
-        }
-        _ => {
-            format!("{}/{}/{}", assets_root, assets_path, index_file)
+    let root = assets_root.trim_end_matches('/');
+    let assets = assets_path.trim_matches('/');
+    let index = index_file.trim_start_matches('/');
+    if assets.is_empty() {
+        format!("{}/{}", root, index)
+    } else {
+        format!("{}/{}/{}", root, assets, index)
+    }
+}
+
+/// Parse the request path forward to upstream
+///
+/// ## Arguments
+///
+/// `router`: route from config file
+/// `req_path`: client request path
+/// `assets_path`: the rest part of client request path
+pub fn parse_proxy_path(router: &SettingRoute, req_path: &str, assets_path: &str) -> String {
+    let assets_path = assets_path.trim_start_matches('/');
+    match &router.proxy_rewrite_prefix {
+        Some(prefix) => {
+            let prefix = prefix.trim_end_matches('/');
+            if assets_path.is_empty() {
+                prefix.to_string()
+            } else {
+                format!("{prefix}/{assets_path}")
+            }
         }
+        None if !router.proxy_strip_prefix => req_path.to_string(),
+        None if !assets_path.is_empty() => format!("/{assets_path}"),
+        None => "".to_string(),
     }
 }
 
@@ -35,17 +66,19 @@ pub fn parse_assets_path(assets_path: &str, assets_root: &str, index_file: &str)
 ///
 /// `req_path`: client request path
 /// `route_map`: router map from config
+/// `fallback`: route used when no location matches
 ///
 /// ## Return
 ///
-/// a result. return none when path not registried
+/// a result. return none when path not registried and no fallback
 /// `router`: host from config file
 /// `assets_path`: the rest part of client request path
 pub fn find_route<'a>(
     req_path: &'a str,
     route_map: &'a HostRouteMap,
+    fallback: Option<&'a SettingRoute>,
 ) -> Result<(&'a SettingRoute, &'a str)> {
-    let not_found_err = format!("resource {} not found", &req_path);
+    let not_found_err = format!("resource {} not found", req_path);
     // /public/www/test
     // convert req path to chars
     let all_chars = req_path.chars().collect::<Vec<_>>();
@@ -67,7 +100,17 @@ pub fn find_route<'a>(
         }
     }
 
-    let (router, assets_path) = last_router.ok_or(Error::NotFound(not_found_err.into()))?;
+    let (router, assets_path) = match (last_router, fallback) {
+        (Some((router, assets_path)), _) => {
+            debug!("location {} matched {req_path}", router.location);
+            (router, assets_path)
+        }
+        (None, Some(fallback)) => {
+            debug!("no location matched {req_path}, use fallback");
+            (fallback, req_path.trim_start_matches('/'))
+        }
+        (None, None) => return Err(Error::NotFound(not_found_err.into())),
+    };
     debug!("router {:?}", &router);
     debug!("assets_path {assets_path}");
     Ok((router, assets_path))
@@ -78,11 +121,18 @@ mod tests {
     use std::collections::BTreeMap;
 
     use super::*;
+    use crate::config::{ByteSize, HumanDuration, ProxyHostHeader};
 
     #[test]
     fn parse_assets_path_works() {
         let path = parse_assets_path("/docs/", "./public", "index.html");
-        assert_eq!(path, "./public/docs/index.html".to_string())
+        assert_eq!(path, "./public/docs/index.html".to_string());
+        let path = parse_assets_path("docs", "./public/", "subdir/index.html");
+        assert_eq!(path, "./public/docs/subdir/index.html".to_string());
+        let path = parse_assets_path("", "./public", "index.html");
+        assert_eq!(path, "./public/index.html".to_string());
+        let path = parse_file_path("docs/style.css", "./public/");
+        assert_eq!(path, "./public/docs/style.css".to_string())
     }
 
     #[test]
@@ -92,11 +142,100 @@ mod tests {
             root: Some("./public".to_string()),
             index: vec!["index.html".into()],
             error_page: None,
+            etag_mode: Default::default(),
+            content_negotiation: false,
+            valid_referers: None,
+            invalid_referer_action: None,
+            protect_extensions: vec![],
+            deny_countries: vec![],
+            headers_file: None,
+            header_rules: None,
             proxy_pass: None,
-            proxy_timeout: 10,
+            split: None,
+            split_key: None,
+            splitter: None,
+            proxy_timeout: HumanDuration::from_secs(10),
+            proxy_connect_timeout_ms: None,
+            proxy_read_timeout_ms: 60_000,
+            proxy_strip_prefix: true,
+            proxy_rewrite_prefix: None,
+            proxy_request_buffering: Default::default(),
+            client_body_buffer_size: ByteSize(16 * 1024),
+            client_max_body_size: None,
+            client_body_temp_path: None,
+            proxy_compress: false,
+            proxy_host_header: ProxyHostHeader::default(),
+            limit_rate: None,
+            limit_rate_after: ByteSize(0),
+            return_route: None,
+        };
+        let fallback = SettingRoute {
+            root: None,
+            proxy_pass: Some("http://localhost:3000".into()),
+            ..setting_route.clone()
+        };
+        let docs = SettingRoute {
+            location: "/docs/".to_string(),
+            ..setting_route.clone()
         };
         let map = BTreeMap::from([("/".to_string(), setting_route)]);
-        let (_, assets_path) = find_route("/docs/home", &map).unwrap();
-        assert_eq!(assets_path, "docs/home")
+        let (_, assets_path) = find_route("/docs/home", &map, None).unwrap();
+        assert_eq!(assets_path, "docs/home");
+
+        // no location matched
+        let map = BTreeMap::from([("/docs/".to_string(), docs)]);
+        let err = find_route("/api/users", &map, None).unwrap_err();
+        assert!(matches!(err, Error::NotFound(_)));
+        let (router, assets_path) = find_route("/api/users", &map, Some(&fallback)).unwrap();
+        assert_eq!(router.proxy_pass.as_deref(), Some("http://localhost:3000"));
+        assert_eq!(assets_path, "api/users");
+        assert_eq!(
+            parse_proxy_path(router, "/api/users", assets_path),
+            "/api/users"
+        );
+    }
+
+    #[test]
+    fn parse_proxy_path_works() {
+        let mut setting_route = SettingRoute {
+            location: "/api/".to_string(),
+            root: None,
+            index: vec![],
+            error_page: None,
+            etag_mode: Default::default(),
+            content_negotiation: false,
+            valid_referers: None,
+            invalid_referer_action: None,
+            protect_extensions: vec![],
+            deny_countries: vec![],
+            headers_file: None,
+            header_rules: None,
+            proxy_pass: Some("http://localhost:3000".into()),
+            split: None,
+            split_key: None,
+            splitter: None,
+            proxy_timeout: HumanDuration::from_secs(10),
+            proxy_connect_timeout_ms: None,
+            proxy_read_timeout_ms: 60_000,
+            proxy_strip_prefix: true,
+            proxy_rewrite_prefix: None,
+            proxy_request_buffering: Default::default(),
+            client_body_buffer_size: ByteSize(16 * 1024),
+            client_max_body_size: None,
+            client_body_temp_path: None,
+            proxy_compress: false,
+            proxy_host_header: ProxyHostHeader::default(),
+            limit_rate: None,
+            limit_rate_after: ByteSize(0),
+            return_route: None,
+        };
+        let path = parse_proxy_path(&setting_route, "/api/users", "users");
+        assert_eq!(path, "/users");
+        setting_route.proxy_strip_prefix = false;
+        let path = parse_proxy_path(&setting_route, "/api/users", "users");
+        assert_eq!(path, "/api/users");
+        setting_route.proxy_rewrite_prefix = Some("/v2/".into());
+        let path = parse_proxy_path(&setting_route, "/api/users", "users");
+        assert_eq!(path, "/v2/users");
     }
 }
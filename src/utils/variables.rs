@@ -0,0 +1,128 @@
+use std::net::SocketAddr;
+
+use http::Request;
+
+use crate::utils::{geoip::country_code, host::parse_host, normalize::OriginalUri};
+
+/// Expand nginx style `$variable` in template with current request
+///
+/// ## Arguments
+///
+/// `template`: text from config file, e.g. `host=$host addr=$remote_addr`
+/// `req`: client request
+/// `peer_addr`: client socket address
+///
+/// ## Return
+///
+/// the expanded string, unknown variables will be kept as it is
+pub fn expand_variables<T>(template: &str, req: &Request<T>, peer_addr: &SocketAddr) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut chars = template.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
+        }
+        // collect variable name
+        let start = i + 1;
+        let mut end = start;
+        while let Some((j, next)) = chars.peek() {
+            if next.is_ascii_alphanumeric() || *next == '_' {
+                end = j + next.len_utf8();
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let name = &template[start..end];
+        match variable_value(name, req, peer_addr) {
+            Some(value) => result.push_str(&value),
+            None => {
+                result.push('$');
+                result.push_str(name);
+            }
+        }
+    }
+    result
+}
+
+/// Find variable value by name
+///
+/// ## Arguments
+///
+/// `name`: variable name without `$`
+/// `req`: client request
+/// `peer_addr`: client socket address
+pub fn variable_value<T>(name: &str, req: &Request<T>, peer_addr: &SocketAddr) -> Option<String> {
+    let value = match name {
+        "host" => req
+            .headers()
+            .get("host")
+            .and_then(|h| h.to_str().ok())
+            .or(req.uri().host())
+            .and_then(|h| parse_host(h).map(|(name, _)| name))
+            .unwrap_or("")
+            .to_string(),
+        "remote_addr" => peer_addr.ip().to_string(),
+        "remote_port" => peer_addr.port().to_string(),
+        "geoip_country_code" => country_code(peer_addr.ip()).to_string(),
+        "request_method" => req.method().to_string(),
+        "uri" => req.uri().path().to_string(),
+        "request_uri" => req
+            .extensions()
+            .get::<OriginalUri>()
+            .map_or(req.uri(), |original| &original.0)
+            .path_and_query()
+            .map(|p| p.to_string())
+            .unwrap_or_default(),
+        "args" | "query_string" => req.uri().query().unwrap_or("").to_string(),
+        "scheme" => req.uri().scheme_str().unwrap_or("http").to_string(),
+        "server_protocol" => format!("{:?}", req.version()),
+        name if name.starts_with("http_") => {
+            let header = name["http_".len()..].replace('_', "-");
+            req.headers()
+                .get(header.as_str())
+                .and_then(|h| h.to_str().ok())
+                .unwrap_or("")
+                .to_string()
+        }
+        name if name.starts_with("arg_") => {
+            let arg = &name["arg_".len()..];
+            req.uri()
+                .query()
+                .unwrap_or("")
+                .split('&')
+                .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+                .find(|(k, _)| *k == arg)
+                .map(|(_, v)| v.to_string())
+                .unwrap_or_default()
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_variables_works() {
+        let req = Request::builder()
+            .uri("/healthz?name=candy")
+            .header("host", "example.com:4000")
+            .header("user-agent", "curl")
+            .body(())
+            .unwrap();
+        let addr: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let text = expand_variables(
+            "host=$host addr=$remote_addr ua=$http_user_agent name=$arg_name $unknown",
+            &req,
+            &addr,
+        );
+        assert_eq!(
+            text,
+            "host=example.com addr=127.0.0.1 ua=curl name=candy $unknown"
+        )
+    }
+}